@@ -1,49 +1,230 @@
-use crate::utilities::parse;
+use crate::error::Error;
+use crate::utilities::includes::{IncludeNode, PlayNode};
+use crate::utilities::options::AnsibleOptions;
+use crate::utilities::parse::PlaySummary;
+use crate::utilities::roles::{self, RoleVar};
+use crate::utilities::vars::ExtraVars;
+use crate::utilities::{includes, parse, playbook, vars, BookEnvs};
 use serde_yaml;
-use std::collections::HashMap as Map;
-use std::path::PathBuf;
+use std::path::Path;
 
 /// Implements the describe command, where the user can view a summary of the playbook
 /// and the full contents of the playbook.
 ///
+/// The summary lists every play with its hosts, roles, task count and the vars it sets, and
+/// the ansible variables the playbook refers to with the plays that refer to them, the ones
+/// it requires apart from the optional ones (see `jinja::VarRef`)
+///
+/// Under every play are the tasks files and roles it pulls in, and the plays of the playbooks
+/// it imports, as a tree, see `includes::playbook_tree`
+///
+/// Every role the playbook runs is listed with where it was found and the variables it takes,
+/// with their type, default and whether they are required, see `roles::role_info`
+///
+/// The summary also gives the extra vars the playbook would be run with, after all the
+/// places they can come from are merged, see `vars::book_vars`, and its ansible options
+///
+/// A playbook that does not exist or cannot be parsed is described with what is wrong with
+/// it instead, and the other playbooks are still described
+///
 /// Flag Verbose:
 /// Return the full contents of the playbook instead of jsut the summary
 ///
 /// # Errors
-/// Returns an error if playbook does not exist
-/// Returns an error if the playbook directory does not exit
-/// Returns an error if yaml can't be parsed
-/// Returns an error if there is no name field in the playbook
+/// Returns an error if the summary cannot be written
 pub fn call_describe(
-    books: &Map<u64, String>,
+    books: &BookEnvs,
     verbose: &bool,
-    playbook: &PathBuf,
+    playbook: &Path,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let mut results = "".to_owned();
-    for (i, book) in books {
-        let mut book_path = playbook.clone();
-        book_path.push(book);
-        if *verbose {
-            let book_content = parse::contents(&book_path)?;
-            results.push_str(&i.to_string());
-            results.push_str(": ");
-            results.push_str(&book);
-            results.push_str("\n===========================\n");
-            results.push_str(&book_content);
+    for (i, (book, book_vars, book_options)) in books {
+        match describe_book(book, book_vars, book_options, verbose, playbook) {
+            Ok(description) => {
+                results.push_str(&i.to_string());
+                results.push_str(": ");
+                results.push_str(book);
+                results.push_str(&description);
+            }
+            Err(err) => results.push_str(&format!("{}: {} - Broken: {}\n\n", i, book, err)),
+        }
+    }
+    Ok(results)
+}
+
+/// Gives the lines `call_describe` gives for the plays of a playbook tree, indented `depth`
+/// levels under `Plays:`
+fn describe_tree(plays: &[PlayNode], depth: usize) -> String {
+    let mut results = String::new();
+    for play in plays {
+        results.push_str(&describe_play(&play.summary, depth));
+        for include in &play.includes {
+            results.push_str(&describe_include(include, depth + 1));
+        }
+    }
+    results
+}
+
+/// Gives the lines `call_describe` gives for a single role, with a line for every variable it
+/// takes under it
+fn describe_role(name: &str, book_dir: &Path, playbook: &Path) -> String {
+    let info = match roles::role_info(name, book_dir) {
+        Ok(Some(info)) => info,
+        Ok(None) => return format!("    {} - not found\n", name),
+        Err(err) => return format!("    {} - Broken: {}\n", name, err),
+    };
+    let path = info.path.strip_prefix(playbook).unwrap_or(&info.path);
+    let mut results = format!("    {} - {}\n", name, path.display());
+    for var in &info.vars {
+        results.push_str(&format!("        {}\n", describe_role_var(var)));
+    }
+    results
+}
+
+/// Gives the line `call_describe` gives for a single variable of a role, e.g.
+/// `nginx_port: int - default: 80`
+fn describe_role_var(var: &RoleVar) -> String {
+    let mut results = format!("{}: {}", var.name, var.var_type.as_deref().unwrap_or("-"));
+    if var.required {
+        results.push_str(" - required");
+    }
+    if let Some(default) = &var.default {
+        // Lists and dicts are given on one line
+        let default = playbook::scalar_text(default)
+            .or_else(|| serde_json::to_string(default).ok())
+            .unwrap_or_default();
+        results.push_str(&format!(" - default: {}", default));
+    }
+    if !var.choices.is_empty() {
+        let choices: Vec<String> = var
+            .choices
+            .iter()
+            .filter_map(playbook::scalar_text)
+            .collect();
+        results.push_str(&format!(" - choices: {}", choices.join(", ")));
+    }
+    results
+}
+
+/// Gives the line `call_describe` gives for a file a play pulls in, and what it pulls in
+/// under it
+fn describe_include(include: &IncludeNode, depth: usize) -> String {
+    let indent = "    ".repeat(depth + 1);
+    let mut results = format!("{}{}: {}", indent, include.kind, include.target);
+    if let Some(tasks_from) = &include.tasks_from {
+        results.push_str(&format!(" (tasks_from: {})", tasks_from));
+    }
+    if let Some(problem) = &include.problem {
+        results.push_str(&format!(" - {}", problem));
+    }
+    results.push('\n');
+    results.push_str(&describe_tree(&include.plays, depth + 1));
+    for nested in &include.includes {
+        results.push_str(&describe_include(nested, depth + 1));
+    }
+    results
+}
+
+/// Gives the line `call_describe` gives for a single play, and the vars it sets under it
+fn describe_play(play: &PlaySummary, depth: usize) -> String {
+    let indent = "    ".repeat(depth + 1);
+    if play.import.is_some() {
+        return format!("{}{}: {}\n", indent, play.number, play.label());
+    }
+    let hosts = if play.hosts.is_empty() {
+        "-".to_owned()
+    } else {
+        play.hosts.join(", ")
+    };
+    let mut results = format!(
+        "{}{}: {} - hosts: {} - {} {}",
+        indent,
+        play.number,
+        play.label(),
+        hosts,
+        play.task_count,
+        if play.task_count == 1 {
+            "task"
         } else {
-            let book_name = parse::unwrap_name(&book_path)?;
-            let book_envs = parse::unwrap_envs(&book_path)?;
-            results.push_str(&i.to_string());
-            results.push_str(": ");
-            results.push_str(&book);
-            results.push_str(" - ");
-            results.push_str(&serde_yaml::to_string(&book_name).unwrap());
-            if !book_envs.is_empty() {
-                results.push_str("Envs: ");
-                results.push_str(&book_envs.join(", "));
+            "tasks"
+        }
+    );
+    if !play.roles.is_empty() {
+        results.push_str(&format!(" - roles: {}", play.roles.join(", ")));
+    }
+    results.push('\n');
+    if !play.vars.is_empty() {
+        results.push_str(&format!(
+            "{}    Play vars: {}\n",
+            indent,
+            play.vars.join(", ")
+        ));
+    }
+    results
+}
+
+/// Gives everything `call_describe` says about a single playbook, after its index and file name
+///
+/// # Errors
+/// Returns an error if playbook does not exist
+/// Returns an error if yaml can't be parsed
+fn describe_book(
+    book: &str,
+    book_vars: &ExtraVars,
+    book_options: &AnsibleOptions,
+    verbose: &bool,
+    playbook: &Path,
+) -> Result<String, Error> {
+    let book_path = playbook.join(book);
+    let mut results = "".to_owned();
+    if *verbose {
+        let book_content = parse::contents(&book_path)?;
+        results.push_str("\n===========================\n");
+        results.push_str(&book_content);
+    } else {
+        let book_name = parse::unwrap_name(&book_path)?;
+        let tree = includes::playbook_tree(&book_path)?;
+        let book_envs = parse::merge_envs(&includes::flatten_plays(&tree));
+        results.push_str(" - ");
+        results.push_str(
+            &serde_yaml::to_string(&book_name).unwrap_or_else(|_| format!("{}\n", book_name)),
+        );
+        results.push_str("Plays:\n");
+        results.push_str(&describe_tree(&tree, 0));
+        let role_names = includes::role_names(&tree);
+        if !role_names.is_empty() {
+            let book_dir = book_path.parent().unwrap_or(playbook);
+            results.push_str("Roles:\n");
+            for role in &role_names {
+                results.push_str(&describe_role(role, book_dir, playbook));
+            }
+        }
+        for (title, optional) in [("Envs", false), ("Optional envs", true)] {
+            let envs: Vec<String> = book_envs
+                .iter()
+                .filter(|(env, _)| env.optional == optional)
+                .map(|(env, plays)| format!("{} ({})", env.name, plays.join(", ")))
+                .collect();
+            if !envs.is_empty() {
+                results.push_str(&format!("{}: {}\n", title, envs.join(", ")));
             }
-            results.push_str("\n");
         }
+        if !book_vars.is_empty() {
+            let mut book_vars: Vec<String> = book_vars
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, vars::format_value(value)))
+                .collect();
+            book_vars.sort();
+            results.push_str("Vars: ");
+            results.push_str(&book_vars.join(", "));
+            results.push('\n');
+        }
+        if !book_options.is_empty() {
+            results.push_str("Options: ");
+            results.push_str(&book_options.to_string());
+            results.push('\n');
+        }
+        results.push('\n');
     }
     Ok(results)
 }
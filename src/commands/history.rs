@@ -0,0 +1,54 @@
+use crate::commands::run::Status;
+use crate::utilities::history;
+use std::path::Path;
+
+/// Implements the history command, where the user can view all the runs
+/// that were saved in the state directory, oldest first
+///
+/// Flag Verbose:
+/// Will also list every playbook of each run, the ansible options it was run with and how it went
+///
+/// # Errors
+/// Returns an error if the state directory cannot be read
+/// Returns an error if a run record is not parsable
+pub fn call_history(
+    verbose: &bool,
+    state_dir: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let mut results = "".to_owned();
+    for run in history::list_runs(state_dir)? {
+        let succeeded = run
+            .books
+            .iter()
+            .filter(|book| book.status == Status::Success)
+            .count();
+        let kind = match run.kind {
+            history::RunKind::Run => "",
+            history::RunKind::Plan => " (plan)",
+        };
+        results.push_str(&format!(
+            "{}{} - {} - {} books, {} succeeded\n",
+            run.id,
+            kind,
+            run.timestamp,
+            run.books.len(),
+            succeeded
+        ));
+        if *verbose {
+            for book in &run.books {
+                if book.options.is_empty() {
+                    results.push_str(&format!(
+                        "    {}: {} - {}\n",
+                        book.index, book.book, book.status
+                    ));
+                } else {
+                    results.push_str(&format!(
+                        "    {}: {} ({}) - {}\n",
+                        book.index, book.book, book.options, book.status
+                    ));
+                }
+            }
+        }
+    }
+    Ok(results)
+}
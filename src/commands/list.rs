@@ -1,20 +1,20 @@
 use crate::utilities::parse;
 use std::collections::HashMap as Map;
-use std::path::PathBuf;
+use std::path::Path;
 
 /// Implements the list command, where the user can view
 /// all the available playbooks in a dir
 ///
 /// Flag Verbose:
-/// Will give the name field of the playbook as well as the playbook file name
+/// Will give the name field of the playbook as well as the playbook file name. A playbook
+/// whose name cannot be read is listed with what is wrong with it instead
 ///
 /// # Erorrs
 /// Returns an error if the directory does not exist
-/// Returns an error if there is no name field in one of the playbooks (with -v)
 pub fn call_list(
     verbose: &bool,
     files: &Map<u64, String>,
-    playbook: &PathBuf,
+    playbook: &Path,
 ) -> Result<String, Box<dyn std::error::Error>> {
     let mut results = "".to_owned();
 
@@ -24,11 +24,12 @@ pub fn call_list(
     files_sorted.sort_by(|a, b| a.0.cmp(b.0));
 
     for (i, file_name) in files_sorted.iter() {
-        let mut book_path = playbook.clone();
-        book_path.push(file_name.clone());
+        let book_path = playbook.join(file_name);
         if *verbose {
-            let book_name = parse::unwrap_name(&book_path)?;
-            let res = format!("{}: {} - {} \n", i, file_name, &book_name);
+            let res = match parse::unwrap_name(&book_path) {
+                Ok(book_name) => format!("{}: {} - {} \n", i, file_name, &book_name),
+                Err(err) => format!("{}: {} - Broken: {}\n", i, file_name, err),
+            };
             results.push_str(&res);
         } else {
             let res = format!("{}: {}\n", i, file_name);
@@ -1,7 +1,15 @@
 pub mod describe;
+pub mod history;
 pub mod list;
+pub mod plan;
 pub mod run;
+pub mod show;
+pub mod validate;
 
 pub use describe::call_describe;
+pub use history::call_history;
 pub use list::call_list;
+pub use plan::call_plan;
 pub use run::call_run;
+pub use show::call_show;
+pub use validate::call_validate;
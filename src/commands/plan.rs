@@ -0,0 +1,154 @@
+use crate::commands::run::{self, BookResult, RunOptions, Status};
+use crate::utilities::{callback, BookEnvs};
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// A task that would change something, with the hosts it would change
+struct PlannedChange<'a> {
+    play: &'a str,
+    task: &'a str,
+    hosts: Vec<&'a str>,
+    /// Every distinct diff of the task, with the hosts that gave it
+    diffs: Vec<(&'a str, Vec<&'a str>)>,
+}
+
+/// Implements the plan command, where the user can see what a batch of playbooks would change
+/// without changing anything.
+///
+/// Every playbook is run with `--check --diff` on top of its own ansible options, and with
+/// ansible's json stdout callback, so the tasks that would change and their diffs can be read
+/// per host. Everything else works like `run::call_run`, e.g. jobs, dependencies and timeouts
+///
+/// # Errors
+/// Returns the same errors as `run::call_run`
+pub fn call_plan(
+    books: &BookEnvs,
+    verbose: &bool,
+    options: &RunOptions,
+    playbook_dir: &Path,
+    inventory: &Path,
+) -> Result<Vec<BookResult>, Box<dyn std::error::Error>> {
+    let mut books = books.clone();
+    for (_, _, book_options) in books.values_mut() {
+        book_options.check = true;
+        book_options.diff = true;
+    }
+    let options = RunOptions {
+        structured: true,
+        ..options.clone()
+    };
+    run::call_run(&books, verbose, &options, playbook_dir, inventory)
+}
+
+/// Implements the plan summary function, which gives every task that would change, with the
+/// hosts it would change and its diff, grouped by playbook, and a line that adds it all up.
+/// Every playbook that could not be checked gets its failed tasks, its log file and the last
+/// `tail` lines of its output instead
+pub fn plan_summary(results: &[BookResult], tail: &usize) -> String {
+    let mut summary = "".to_owned();
+    let mut change_count = 0;
+    let mut changed_hosts = BTreeSet::new();
+    let mut unchecked = 0;
+    for result in results {
+        if result.status != Status::Success {
+            unchecked += 1;
+            summary.push_str(&format!(
+                "{}: {} - {}\n",
+                result.index, result.book, result.status
+            ));
+            summary.push_str(&callback::task_failures(&result.tasks, "    "));
+            summary.push_str(&run::log_tail(result, tail));
+            continue;
+        }
+
+        let changes = planned_changes(result);
+        match changes.len() {
+            0 => summary.push_str(&format!("{}: {} - No changes\n", result.index, result.book)),
+            1 => summary.push_str(&format!(
+                "{}: {} - 1 task would change\n",
+                result.index, result.book
+            )),
+            count => summary.push_str(&format!(
+                "{}: {} - {} tasks would change\n",
+                result.index, result.book, count
+            )),
+        }
+        for change in &changes {
+            summary.push_str(&format!(
+                "    ~ {} / {} on {}\n",
+                change.play,
+                change.task,
+                change.hosts.join(", ")
+            ));
+            for (diff, hosts) in &change.diffs {
+                let mut indent = "        ";
+                if change.diffs.len() > 1 {
+                    summary.push_str(&format!("        {}:\n", hosts.join(", ")));
+                    indent = "          ";
+                }
+                for line in diff.lines() {
+                    summary.push_str(&format!("{}{}\n", indent, line));
+                }
+            }
+        }
+        change_count += changes.len();
+        changed_hosts.extend(changes.iter().flat_map(|change| change.hosts.clone()));
+    }
+
+    summary.push_str(&format!(
+        "\nPlan: {} {} would change on {} {}",
+        change_count,
+        if change_count == 1 { "task" } else { "tasks" },
+        changed_hosts.len(),
+        if changed_hosts.len() == 1 {
+            "host"
+        } else {
+            "hosts"
+        }
+    ));
+    if !changed_hosts.is_empty() {
+        let hosts: Vec<&str> = changed_hosts.into_iter().collect();
+        summary.push_str(&format!(" ({})", hosts.join(", ")));
+    }
+    if unchecked > 0 {
+        summary.push_str(&format!(
+            ", {} {} could not be checked",
+            unchecked,
+            if unchecked == 1 {
+                "playbook"
+            } else {
+                "playbooks"
+            }
+        ));
+    }
+    summary.push('\n');
+    summary
+}
+
+/// Gives the tasks of a playbook that would change something, in the order ansible ran them
+fn planned_changes(result: &BookResult) -> Vec<PlannedChange<'_>> {
+    let mut changes: Vec<PlannedChange> = Vec::new();
+    for task in result.tasks.iter().filter(|task| task.changed) {
+        let index = changes
+            .iter()
+            .position(|change| change.play == task.play && change.task == task.task)
+            .unwrap_or_else(|| {
+                changes.push(PlannedChange {
+                    play: &task.play,
+                    task: &task.task,
+                    hosts: Vec::new(),
+                    diffs: Vec::new(),
+                });
+                changes.len() - 1
+            });
+        let change = &mut changes[index];
+        change.hosts.push(&task.host);
+        if let Some(diff) = &task.diff {
+            match change.diffs.iter_mut().find(|(other, _)| other == diff) {
+                Some((_, hosts)) => hosts.push(&task.host),
+                None => change.diffs.push((diff, vec![&task.host])),
+            }
+        }
+    }
+    changes
+}
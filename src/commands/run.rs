@@ -1,128 +1,654 @@
+use crate::error::Error;
+use crate::utilities::callback::{self, TaskResult};
+use crate::utilities::options::AnsibleOptions;
+use crate::utilities::recap::{self, HostStats};
+use crate::utilities::stream::{self, StreamOptions};
+use crate::utilities::vars::ExtraVars;
+use crate::utilities::{deps, parse, validate, BookEnvs};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap as Map;
-use std::io::{BufRead, BufReader};
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often a running command is checked on while it has a timeout
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long a timed out command gets to exit after SIGTERM before it is killed
+const KILL_GRACE: Duration = Duration::from_secs(5);
+
+/// The longest a retry waits, however large the backoff makes the delay
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The outcome of a single playbook in a batch
+#[derive(Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Status {
+    Success,
+    Failed,
+    Skipped,
+    TimedOut,
+    Cancelled,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Status::Success => write!(f, "Success"),
+            Status::Failed => write!(f, "Failed"),
+            Status::Skipped => write!(f, "Skipped (dependency failed)"),
+            Status::TimedOut => write!(f, "Timed out"),
+            Status::Cancelled => write!(f, "Cancelled (fail fast)"),
+        }
+    }
+}
+
+/// How a batch of playbooks is run, see `call_run`
+#[derive(Clone)]
+pub struct RunOptions {
+    pub validate: bool,
+    pub jobs: usize,
+    pub structured: bool,
+    pub prefix: bool,
+    pub timestamps: bool,
+    pub log_dir: PathBuf,
+    pub fail_fast: bool,
+    pub timeout: Option<Duration>,
+    pub retry: RetryPolicy,
+}
+
+/// How often and how quickly a failed playbook is run again
+#[derive(Clone)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub delay: Duration,
+    pub backoff: f64,
+}
+
+impl RetryPolicy {
+    /// Gives the policy for a single playbook, where its `# retries:`, `# retry_delay:` and
+    /// `# retry_backoff:` header comments win over this policy
+    ///
+    /// # Errors
+    /// Returns an error if the playbook is not found
+    /// Returns an error if one of the header comments is not valid
+    pub fn for_book(&self, path: &PathBuf) -> Result<RetryPolicy, Error> {
+        let invalid = |key: &str, message: String| Error::InvalidHeader {
+            path: path.clone(),
+            key: key.to_owned(),
+            message,
+        };
+        let mut policy = self.clone();
+        if let Some(retries) = parse::unwrap_header(path, "retries")? {
+            policy.retries = retries
+                .parse()
+                .map_err(|err| invalid("retries", format!("{}: {}", retries, err)))?;
+        }
+        if let Some(delay) = parse::unwrap_header(path, "retry_delay")? {
+            policy.delay = humantime::parse_duration(&delay)
+                .map_err(|err| invalid("retry_delay", format!("{}: {}", delay, err)))?;
+        }
+        if let Some(backoff) = parse::unwrap_header(path, "retry_backoff")? {
+            policy.backoff =
+                parse_backoff(&backoff).map_err(|message| invalid("retry_backoff", message))?;
+        }
+        Ok(policy)
+    }
+
+    /// Gives how many times a playbook is run at most
+    pub fn attempts(&self) -> u32 {
+        self.retries + 1
+    }
+
+    /// Gives how long to wait before the given attempt, starting at `delay` before the
+    /// second attempt and growing by `backoff` each time after that, up to a day
+    pub fn delay_before(&self, attempt: u32) -> Duration {
+        if self.delay.is_zero() {
+            return Duration::ZERO;
+        }
+        let exponent = i32::try_from(attempt.saturating_sub(2)).unwrap_or(i32::MAX);
+        let seconds = self.delay.as_secs_f64() * self.backoff.max(0.0).powi(exponent);
+        Duration::try_from_secs_f64(seconds)
+            .unwrap_or(MAX_RETRY_DELAY)
+            .min(MAX_RETRY_DELAY)
+    }
+}
+
+/// Implements the parse backoff function, which reads a retry backoff, a number the delay
+/// between retries is multiplied by after every retry
+///
+/// # Errors
+/// Returns an error if the backoff is not a number, or is negative, infinite or NaN
+pub fn parse_backoff(backoff: &str) -> Result<f64, String> {
+    let value: f64 = backoff
+        .trim()
+        .parse()
+        .map_err(|err| format!("{}: {}", backoff, err))?;
+    if !value.is_finite() || value < 0.0 {
+        return Err(format!(
+            "{}: backoff has to be a finite number that is not negative",
+            backoff
+        ));
+    }
+    Ok(value)
+}
+
+/// The record of a single playbook in a batch
+#[derive(Clone)]
+pub struct BookResult {
+    pub index: u64,
+    pub book: String,
+    pub extra_vars: ExtraVars,
+    pub options: AnsibleOptions,
+    pub status: Status,
+    pub attempt: u32,
+    pub attempts: u32,
+    pub start: Option<SystemTime>,
+    pub end: Option<SystemTime>,
+    pub exit_code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+    pub log: Option<PathBuf>,
+    /// Whether the cli could not run the playbook at all, e.g. `ansible-playbook` is not on
+    /// PATH, in which case `stderr` says why
+    pub tool_error: bool,
+    pub hosts: Vec<HostStats>,
+    pub tasks: Vec<TaskResult>,
+}
+
+impl BookResult {
+    /// Gives the time the playbook took to run, or zero if it never ran
+    pub fn duration(&self) -> Duration {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => end.duration_since(start).unwrap_or_default(),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Gives the record of a playbook that has not run (yet)
+    fn not_run(
+        index: u64,
+        book: &str,
+        envs: &ExtraVars,
+        options: &AnsibleOptions,
+        attempts: u32,
+    ) -> BookResult {
+        BookResult {
+            index,
+            book: book.to_owned(),
+            extra_vars: envs.clone(),
+            options: options.clone(),
+            status: Status::Skipped,
+            attempt: 0,
+            attempts,
+            start: None,
+            end: None,
+            exit_code: None,
+            stdout: String::new(),
+            stderr: String::new(),
+            log: None,
+            tool_error: false,
+            hosts: Vec::new(),
+            tasks: Vec::new(),
+        }
+    }
+}
+
+/// What came out of running a single command
+struct Execution {
+    exit_code: Option<i32>,
+    timed_out: bool,
+    stdout: String,
+    stderr: String,
+}
 
 /// Implements the run command, where a user can run a playbook(s)
 ///
+/// With `options.validate` nothing is run if a playbook requires variables it was not given
+/// as extra vars (see `validate::missing_vars`), or is given extra vars that do not fit the
+/// argument specs of its roles (see `validate::role_var_mismatches`).
+///
+/// Up to `options.jobs` playbooks are run at the same time. The results are always
+/// returned in index order, no matter what order the playbooks finish in.
+///
+/// Playbooks can declare dependencies with a `# depends_on:` header comment. A playbook is
+/// only started once all the playbooks it depends on in the batch have succeeded, and is
+/// skipped if any of them failed.
+///
+/// A playbook that runs for longer than its `# timeout:` header comment, or `options.timeout` if it
+/// has none, has its whole process group killed and is reported as timed out.
+///
+/// A playbook that does not succeed is run again as `options.retry` says, which playbooks can
+/// override with header comments, see `RetryPolicy::for_book`. The stdio of the last
+/// attempt is kept.
+///
+/// Every playbook is run with its own ansible options, e.g. `--limit` or `--check`, see
+/// `AnsibleOptions`.
+///
+/// With `options.structured` ansible's json stdout callback is used, and the hosts, tasks and
+/// status of every playbook are read from its output.
+///
+/// Flag Fail Fast:
+/// No more playbooks are started once one has not succeeded. The playbooks that were already
+/// running are waited for, the rest are reported as cancelled
+///
+/// The output of every playbook is captured, and written to `<index>-<book>.log` in
+/// `options.log_dir` as it runs.
+///
 /// Flag Verbose:
-/// Instead of just viewing whether a command succeeded or failed, view all of stdio
+/// Instead of just viewing whether a command succeeded or failed, view all of stdio as it
+/// arrives. When more than one job is allowed, or `options.prefix` is set, every line is
+/// prefixed with the playbook it came from. `options.timestamps` puts the time in front of
+/// every line
 ///
 /// # Errors
 /// Returns an error if the playbook(s) is not found
 /// Returns an error if the playbook directory does not exist
 /// Returns an error if the inventory file is not found
+/// Returns an error if the playbook dependencies contain a cycle
+/// Returns an error if a playbook is missing variables (with `options.validate`)
+/// Returns an error if an extra var does not fit the argument spec of a role (with
+/// `options.validate`)
 ///
 /// Sample command that will be run
-/// ansible-playbook -i ../inventory.yaml install_ior.yaml
+/// ansible-playbook -i ../inventory.yaml --limit web install_ior.yaml
 pub fn call_run(
-    books: &Map<u64, (String, Map<String, String>)>,
+    books: &BookEnvs,
     verbose: &bool,
-    playbook_dir: &PathBuf,
-    inventory: &PathBuf,
-) -> Result<String, Box<dyn std::error::Error>> {
-    let mut results = "".to_owned();
-
-    let mut args = Vec::new();
-    args.push("-i".to_owned());
-    args.push(inventory.to_str().unwrap().to_owned());
-
-    for (i, book_map) in books {
-        let book = book_map.0.clone();
-        let envs = book_map.1.clone();
-        let mut book_path = playbook_dir.clone();
-        book_path.push(book.clone());
-        args.push(book_path.to_str().unwrap().to_owned());
-
-        if *verbose {
-            if let Ok(true) =
-                run_command_verbose("ansible-playbook".to_owned(), args.clone(), envs.clone())
-            {
-                results.push_str(&format!("{}: {} - Success\n", i, book));
-            } else {
-                results.push_str(&format!("{}: {} - Failed\n", i, book));
+    options: &RunOptions,
+    playbook_dir: &Path,
+    inventory: &Path,
+) -> Result<Vec<BookResult>, Box<dyn std::error::Error>> {
+    check_inventory(inventory)?;
+    if options.validate {
+        let missing = validate::missing_vars(books, playbook_dir, inventory)?;
+        if !missing.is_empty() {
+            return Err(validate::missing_vars_error(books, &missing).into());
+        }
+        let mismatches = validate::role_var_mismatches(books, playbook_dir)?;
+        if !mismatches.is_empty() {
+            return Err(validate::role_var_mismatches_error(books, &mismatches).into());
+        }
+    }
+
+    let jobs = options.jobs.max(1);
+    let graph = deps::dependency_graph(books, playbook_dir)?;
+
+    let mut books_sorted: Vec<_> = books.iter().collect();
+    books_sorted.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut timeouts = Map::new();
+    let mut retries = Map::new();
+    for (i, (book, _, _)) in &books_sorted {
+        let book_path = playbook_dir.join(book);
+        let book_timeout = parse::unwrap_timeout(&book_path)?;
+        timeouts.insert(**i, book_timeout.or(options.timeout));
+        retries.insert(**i, options.retry.for_book(&book_path)?);
+    }
+
+    fs::create_dir_all(&options.log_dir)?;
+
+    let mut outcomes: Map<u64, BookResult> = Map::new();
+    thread::scope(|scope| {
+        let (tx, rx) = mpsc::channel();
+        let mut pending = books_sorted.clone();
+        let mut running = 0;
+        loop {
+            let failed = outcomes
+                .values()
+                .any(|result| result.status != Status::Success);
+            if options.fail_fast && failed {
+                for (i, (book, envs, book_options)) in pending.drain(..) {
+                    let attempts = retries[i].attempts();
+                    let mut result = BookResult::not_run(*i, book, envs, book_options, attempts);
+                    result.status = Status::Cancelled;
+                    outcomes.insert(*i, result);
+                }
             }
-        } else {
-            if let Ok(true) = run_command("ansible-playbook".to_owned(), args.clone(), envs.clone())
-            {
-                results.push_str(&format!("{}: {} - Success\n", i, book));
-            } else {
-                results.push_str(&format!("{}: {} - Failed\n", i, book));
+            while running < jobs {
+                let ready = pending
+                    .iter()
+                    .position(|(i, _)| graph[i].iter().all(|dep| outcomes.contains_key(dep)));
+                let Some(ready) = ready else {
+                    break;
+                };
+                let (i, (book, envs, book_options)) = pending.remove(ready);
+                let attempts = retries[i].attempts();
+                let mut result = BookResult::not_run(*i, book, envs, book_options, attempts);
+                if graph[i]
+                    .iter()
+                    .any(|dep| outcomes[dep].status != Status::Success)
+                {
+                    outcomes.insert(*i, result);
+                    continue;
+                }
+                let mut args = vec!["-i".to_owned(), inventory.to_string_lossy().to_string()];
+                args.extend(book_options.to_args());
+                args.push(playbook_dir.join(book).to_string_lossy().to_string());
+                let stream = StreamOptions {
+                    echo: *verbose,
+                    prefix: if jobs > 1 || options.prefix {
+                        Some(format!("[{}: {}] ", i, book))
+                    } else {
+                        None
+                    },
+                    timestamps: options.timestamps,
+                    log: Some(options.log_dir.join(format!("{}-{}.log", i, book))),
+                };
+                let timeout = timeouts[i];
+                let tx = tx.clone();
+                let retry = retries[i].clone();
+                result.start = Some(SystemTime::now());
+                result.log = stream.log.clone();
+                // A worker that panics still has to report back, or the batch waits on it
+                // forever
+                let mut crashed = result.clone();
+                scope.spawn(move || {
+                    let run = panic::AssertUnwindSafe(|| {
+                        run_book(
+                            result,
+                            &retry,
+                            &args,
+                            envs,
+                            timeout,
+                            &stream,
+                            options.structured,
+                        )
+                    });
+                    let result = panic::catch_unwind(run).unwrap_or_else(|payload| {
+                        crashed.status = Status::Failed;
+                        crashed.tool_error = true;
+                        crashed.stderr =
+                            format!("Crashed while running: {}", panic_message(&*payload));
+                        crashed.end = Some(SystemTime::now());
+                        crashed
+                    });
+                    tx.send(result).ok();
+                });
+                running += 1;
+            }
+            if running == 0 {
+                break;
+            }
+            match rx.recv() {
+                Ok(result) => {
+                    outcomes.insert(result.index, result);
+                    running -= 1;
+                }
+                Err(_) => break,
             }
         }
-        args.pop();
-    }
+    });
+
+    let mut results: Vec<BookResult> = outcomes.into_values().collect();
+    results.sort_by_key(|result| result.index);
     Ok(results)
 }
 
-/// Implements a simple run command. Returns true if the command succeeded, false otherwise
+/// Runs a single playbook until it succeeds or runs out of attempts, see `RetryPolicy`.
+/// With `structured` it is run with the json callback
+fn run_book(
+    mut result: BookResult,
+    retry: &RetryPolicy,
+    args: &[String],
+    envs: &ExtraVars,
+    timeout: Option<Duration>,
+    stream: &StreamOptions,
+    structured: bool,
+) -> BookResult {
+    let command_envs = if structured {
+        vec![callback::STDOUT_CALLBACK]
+    } else {
+        Vec::new()
+    };
+    for attempt in 1..=retry.attempts() {
+        if attempt > 1 {
+            thread::sleep(retry.delay_before(attempt));
+        }
+        result.attempt = attempt;
+        let outcome = run_command(
+            "ansible-playbook".to_owned(),
+            args.to_vec(),
+            envs.clone(),
+            &command_envs,
+            timeout,
+            stream.clone(),
+        );
+        record_outcome(&mut result, outcome, structured);
+        if result.status == Status::Success {
+            break;
+        }
+    }
+    result.end = Some(SystemTime::now());
+    result
+}
+
+/// Gives the message a panic was raised with
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "unknown panic".to_owned()
+    }
+}
+
+/// Implements the check inventory function, which makes sure the inventory exists before
+/// anything is run. Inventories given as a comma separated list of hosts, e.g. `node1,node2,`,
+/// are not files and are not checked
 ///
 /// # Errors
-/// Returns an error if the command fails to execute
-fn run_command(
-    cmd: String,
-    mut args: Vec<String>,
-    envs: Map<String, String>,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    // take envs map, and create a vector of strings, where you push -e and then key=value
-    for (key, value) in envs {
-        args.push(format!("-e {}={}", key, value));
-    }
-    let status = Command::new(cmd)
-        .args(args)
-        .stdout(Stdio::null())
-        .stderr(Stdio::null())
-        .status()
-        .expect("Failed to execute command");
-    if status.success() {
-        Ok(true)
+/// Returns an error if the inventory does not exist
+pub fn check_inventory(inventory: &Path) -> Result<(), Error> {
+    if inventory.exists() || inventory.to_string_lossy().contains(',') {
+        Ok(())
     } else {
-        Ok(false)
+        Err(Error::InventoryMissing(inventory.to_path_buf()))
+    }
+}
+
+/// Implements the summary function, which gives a line for every playbook in a batch
+/// saying whether it succeeded or failed, and on which attempt if it could be retried,
+/// followed by the PLAY RECAP counts of every host it ran on and any tasks that failed.
+/// Every playbook that ran and did not succeed also gets its log file and the last
+/// `tail` lines of its output
+pub fn summary(results: &[BookResult], tail: &usize) -> String {
+    let mut summary = "".to_owned();
+    for result in results {
+        if result.attempts > 1 && result.attempt > 0 {
+            summary.push_str(&format!(
+                "{}: {} - {} (attempt {}/{})\n",
+                result.index, result.book, result.status, result.attempt, result.attempts
+            ));
+        } else {
+            summary.push_str(&format!(
+                "{}: {} - {}\n",
+                result.index, result.book, result.status
+            ));
+        }
+        if !result.hosts.is_empty() {
+            summary.push_str(&recap::recap_table(&result.hosts, "    "));
+        }
+        summary.push_str(&callback::task_failures(&result.tasks, "    "));
+        if result.status != Status::Success {
+            summary.push_str(&log_tail(result, tail));
+        }
     }
+    summary
 }
 
-/// Implements a verbose run command. Returns true if the command succeeded, false otherwise
-/// Prints all of the stdio of the command
+/// Implements the log tail function, which gives the log file of a playbook that ran and the
+/// last `tail` lines of its output. When there is no log file, because the playbook could not
+/// be run at all, its error output is given instead
+pub fn log_tail(result: &BookResult, tail: &usize) -> String {
+    let mut lines = "".to_owned();
+    let log = result.log.as_ref().filter(|log| log.is_file());
+    let output = match log {
+        Some(log) => {
+            lines.push_str(&format!("    Log: {}\n", log.display()));
+            fs::read_to_string(log).unwrap_or_default()
+        }
+        None => result.stderr.clone(),
+    };
+    let output: Vec<&str> = output.lines().collect();
+    for line in &output[output.len().saturating_sub(*tail)..] {
+        lines.push_str(&format!("    | {}\n", line));
+    }
+    lines
+}
+
+/// Fills in the status and stdio of a playbook from a single attempt at running it.
+/// With `structured` the stdout is read as json callback output, and a host that failed
+/// or could not be reached fails the playbook even if ansible exited with 0
+fn record_outcome(
+    result: &mut BookResult,
+    outcome: Result<Execution, Box<dyn std::error::Error>>,
+    structured: bool,
+) {
+    match outcome {
+        Ok(execution) => {
+            result.status = if execution.timed_out {
+                Status::TimedOut
+            } else if execution.exit_code == Some(0) {
+                Status::Success
+            } else {
+                Status::Failed
+            };
+            result.exit_code = execution.exit_code;
+            result.hosts = recap::parse_recap(&execution.stdout);
+            result.tasks = Vec::new();
+            if structured {
+                if let Ok(output) = callback::parse_output(&execution.stdout) {
+                    result.hosts = output.host_stats();
+                    result.tasks = output.task_results();
+                }
+                let host_failed = result
+                    .hosts
+                    .iter()
+                    .any(|stats| stats.failed > 0 || stats.unreachable > 0);
+                if result.status == Status::Success && host_failed {
+                    result.status = Status::Failed;
+                }
+            }
+            result.stdout = execution.stdout;
+            result.stderr = execution.stderr;
+            result.tool_error = false;
+        }
+        Err(err) => {
+            result.status = Status::Failed;
+            result.tool_error = true;
+            result.exit_code = None;
+            result.hosts = Vec::new();
+            result.tasks = Vec::new();
+            result.stdout = String::new();
+            result.stderr = format!("Could not run ansible-playbook: {}", err);
+        }
+    }
+}
+
+/// Implements the run command function. Handles the stdio of the command as `stream` says
+/// while it runs, and captures all of it
 ///
 /// # Errors
 /// Returns an error if the command fails to execute
 /// Returns an error if the command fails to open stdout
 /// Returns an error if the command fails to open stderr
+/// Returns an error if the log file cannot be written
 /// Returns an error if the command fails to wait
-fn run_command_verbose(
+fn run_command(
     cmd: String,
     mut args: Vec<String>,
-    envs: Map<String, String>,
-) -> Result<bool, Box<dyn std::error::Error>> {
-    for (key, value) in envs {
-        args.push(format!("-e {}={}", key, value));
+    envs: ExtraVars,
+    command_envs: &[(&str, &str)],
+    timeout: Option<Duration>,
+    stream: StreamOptions,
+) -> Result<Execution, Box<dyn std::error::Error>> {
+    // pass all the extra vars as a single json document, so values keep their types and
+    // can hold spaces, quotes, commas and equals signs
+    if !envs.is_empty() {
+        args.push("-e".to_owned());
+        args.push(serde_json::to_string(&envs)?);
     }
-    let mut command = Command::new(cmd)
-        .args(args)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to execute command");
+    let mut command = spawn_command(cmd, args, command_envs)?;
 
-    let stdout = command.stdout.take().expect("Failed to open stdout");
-    let stderr = command.stderr.take().expect("Failed to open stdout");
+    let stdout = command.stdout.take().ok_or("Failed to open stdout")?;
+    let stderr = command.stderr.take().ok_or("Failed to open stderr")?;
+    let reader = match stream::stream(stdout, stderr, stream) {
+        Ok(reader) => reader,
+        Err(err) => {
+            command.kill().ok();
+            command.wait().ok();
+            return Err(err.into());
+        }
+    };
 
-    let stdoutreader = BufReader::new(stdout);
-    let stderrreader = BufReader::new(stderr);
+    let status = wait_with_timeout(&mut command, timeout)?;
+    let captured = reader.join().map_err(|_| "Failed to read output")??;
+    Ok(Execution {
+        exit_code: status.and_then(|status| status.code()),
+        timed_out: status.is_none(),
+        stdout: captured.stdout,
+        stderr: captured.stderr,
+    })
+}
 
-    let stdoutlines = stdoutreader.lines();
-    let stderrlines = stderrreader.lines();
+/// Starts the command in its own process group with piped stdio, so that the command and
+/// everything it forks can be killed together. `command_envs` are set in its environment
+///
+/// # Errors
+/// Returns an error if the command fails to execute
+fn spawn_command(
+    cmd: String,
+    args: Vec<String>,
+    command_envs: &[(&str, &str)],
+) -> Result<Child, Box<dyn std::error::Error>> {
+    let command = Command::new(cmd)
+        .args(args)
+        .envs(command_envs.iter().copied())
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    Ok(command)
+}
 
-    for line in stdoutlines.chain(stderrlines) {
-        if let Ok(line) = line {
-            println!("{}", line);
+/// Waits for the command to exit. Returns None if it ran for longer than `timeout`, in which
+/// case its whole process group is terminated, and killed if it does not exit within
+/// `KILL_GRACE`
+///
+/// # Errors
+/// Returns an error if the command fails to wait
+fn wait_with_timeout(
+    command: &mut Child,
+    timeout: Option<Duration>,
+) -> Result<Option<ExitStatus>, Box<dyn std::error::Error>> {
+    let Some(timeout) = timeout else {
+        return Ok(Some(command.wait()?));
+    };
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        if let Some(status) = command.try_wait()? {
+            return Ok(Some(status));
         }
+        thread::sleep(POLL_INTERVAL);
     }
 
-    let status = command.wait().expect("Failed to wait for command");
-    if status.success() {
-        Ok(true)
-    } else {
-        Ok(false)
+    let group = command.id() as libc::pid_t;
+    // SAFETY: kill only sends a signal, a negative pid targets the process group
+    unsafe { libc::kill(-group, libc::SIGTERM) };
+    let deadline = Instant::now() + KILL_GRACE;
+    while Instant::now() < deadline && command.try_wait()?.is_none() {
+        thread::sleep(POLL_INTERVAL);
     }
+    // SAFETY: as above, this also catches forks that outlived the command itself
+    unsafe { libc::kill(-group, libc::SIGKILL) };
+    command.wait()?;
+    Ok(None)
 }
@@ -0,0 +1,70 @@
+use crate::utilities::callback;
+use crate::utilities::history;
+use crate::utilities::recap;
+use crate::utilities::vars;
+use std::fs;
+use std::path::Path;
+
+/// Implements the show command, where the user can view a single saved run:
+/// what was run, against which inventory, with which extra vars and ansible options and how
+/// it went
+///
+/// Flag Verbose:
+/// Will also give the full log of every playbook in the run
+///
+/// # Errors
+/// Returns an error if the run does not exist
+/// Returns an error if the run record is not parsable
+/// Returns an error if a log file cannot be read (with -v)
+pub fn call_show(
+    run_id: &str,
+    verbose: &bool,
+    state_dir: &Path,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let run = history::load_run(state_dir, run_id)?;
+    let mut results = format!(
+        "{} {} - {}\nPlaybook dir: {}\nInventory: {}\n",
+        run.kind,
+        run.id,
+        run.timestamp,
+        run.playbook_dir.display(),
+        run.inventory.display()
+    );
+    for book in &run.books {
+        if book.attempt > 1 {
+            results.push_str(&format!(
+                "{}: {} - {} on attempt {} ({:.1}s)\n",
+                book.index, book.book, book.status, book.attempt, book.duration_secs
+            ));
+        } else {
+            results.push_str(&format!(
+                "{}: {} - {} ({:.1}s)\n",
+                book.index, book.book, book.status, book.duration_secs
+            ));
+        }
+        if !book.extra_vars.is_empty() {
+            let mut extra_vars: Vec<String> = vars::redact(&book.extra_vars)
+                .iter()
+                .map(|(key, value)| format!("{}={}", key, vars::format_value(value)))
+                .collect();
+            extra_vars.sort();
+            results.push_str(&format!("    Extra vars: {}\n", extra_vars.join(", ")));
+        }
+        if !book.options.is_empty() {
+            results.push_str(&format!("    Options: {}\n", book.options));
+        }
+        if !book.hosts.is_empty() {
+            results.push_str(&recap::recap_table(&book.hosts, "    "));
+        }
+        results.push_str(&callback::task_failures(&book.tasks, "    "));
+        if let Some(log) = &book.log {
+            results.push_str(&format!("    Log: {}\n", log.display()));
+            if *verbose {
+                results.push_str("===========================\n");
+                results.push_str(&fs::read_to_string(log)?);
+                results.push_str("===========================\n");
+            }
+        }
+    }
+    Ok(results)
+}
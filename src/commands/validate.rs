@@ -0,0 +1,157 @@
+use crate::commands::run;
+use crate::error::Error;
+use crate::utilities::{includes, parse};
+use std::collections::HashMap as Map;
+use std::path::Path;
+use std::process::Command;
+
+/// The outcome of checking a single playbook
+pub struct BookValidation {
+    pub index: u64,
+    pub book: String,
+    /// What is wrong with the shape of the playbook, see `parse::structure_errors`, and the
+    /// files it pulls in that cannot be followed, see `includes::include_problems`
+    pub structure_errors: Vec<String>,
+    /// How `ansible-playbook --syntax-check` went, with its output if it failed. None when
+    /// it was not run
+    pub syntax: Option<Result<(), String>>,
+}
+
+impl BookValidation {
+    /// Gives whether every check that was run passed
+    pub fn passed(&self) -> bool {
+        self.structure_errors.is_empty() && !matches!(self.syntax, Some(Err(_)))
+    }
+}
+
+/// Implements the validate command, where the user can check playbooks before running them.
+///
+/// Every playbook is first checked for its shape, see `parse::structure_errors`, and then for
+/// imported and included files that are missing or include themselves, see
+/// `includes::playbook_tree`. With
+/// `syntax_check`, every playbook whose shape is fine is then also checked by
+/// `ansible-playbook --syntax-check -i <inventory>`. A playbook that fails a check does not
+/// stop the others from being checked
+///
+/// Flag Verbose:
+/// Give all the output of a syntax check that failed, not just its errors
+///
+/// # Errors
+/// Returns an error if the playbook directory does not exist
+/// Returns an error if the inventory does not exist (with `syntax_check`)
+pub fn call_validate(
+    files: &Map<u64, String>,
+    syntax_check: bool,
+    verbose: &bool,
+    playbook_dir: &Path,
+    inventory: &Path,
+) -> Result<Vec<BookValidation>, Box<dyn std::error::Error>> {
+    if !playbook_dir.is_dir() {
+        return Err(Error::PlaybookDirNotFound(playbook_dir.to_path_buf()).into());
+    }
+    if syntax_check {
+        run::check_inventory(inventory)?;
+    }
+    let mut files_sorted: Vec<(&u64, &String)> = files.iter().collect();
+    files_sorted.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut results = Vec::new();
+    for (i, book) in files_sorted {
+        let book_path = playbook_dir.join(book);
+        let mut structure_errors = parse::structure_errors(&book_path);
+        if structure_errors.is_empty() {
+            match includes::playbook_tree(&book_path) {
+                Ok(tree) => structure_errors.extend(includes::include_problems(&tree)),
+                Err(err) => structure_errors.push(err.to_string()),
+            }
+        }
+        let syntax = if syntax_check && structure_errors.is_empty() {
+            Some(run_syntax_check(&book_path, inventory, verbose))
+        } else {
+            None
+        };
+        results.push(BookValidation {
+            index: *i,
+            book: book.clone(),
+            structure_errors,
+            syntax,
+        });
+    }
+    Ok(results)
+}
+
+/// Implements the validation table function, which lays out how every playbook did as a
+/// table, with what went wrong under every playbook that failed, and a line that adds it
+/// all up
+pub fn validation_table(results: &[BookValidation]) -> String {
+    let width = results
+        .iter()
+        .map(|result| result.book.len())
+        .chain(std::iter::once("PLAYBOOK".len()))
+        .max()
+        .unwrap_or_default();
+    let mut table = format!(
+        "{:>3}  {:<width$}  {:<9}  {}\n",
+        "#",
+        "PLAYBOOK",
+        "STRUCTURE",
+        "SYNTAX",
+        width = width
+    );
+    for result in results {
+        let structure = if result.structure_errors.is_empty() {
+            "passed"
+        } else {
+            "failed"
+        };
+        let syntax = match &result.syntax {
+            Some(Ok(())) => "passed",
+            Some(Err(_)) => "failed",
+            None => "-",
+        };
+        table.push_str(&format!(
+            "{:>3}  {:<width$}  {:<9}  {}\n",
+            result.index,
+            result.book,
+            structure,
+            syntax,
+            width = width
+        ));
+        for error in &result.structure_errors {
+            table.push_str(&format!("     | {}\n", error));
+        }
+        if let Some(Err(output)) = &result.syntax {
+            for line in output.lines().filter(|line| !line.trim().is_empty()) {
+                table.push_str(&format!("     | {}\n", line));
+            }
+        }
+    }
+    let failed = results.iter().filter(|result| !result.passed()).count();
+    table.push_str(&format!(
+        "\n{} checked, {} passed, {} failed\n",
+        results.len(),
+        results.len() - failed,
+        failed
+    ));
+    table
+}
+
+/// Runs `ansible-playbook --syntax-check` on a single playbook. Gives the errors it printed
+/// if it failed, or all of its output with `verbose`
+fn run_syntax_check(book_path: &Path, inventory: &Path, verbose: &bool) -> Result<(), String> {
+    let output = Command::new("ansible-playbook")
+        .arg("--syntax-check")
+        .arg("-i")
+        .arg(inventory)
+        .arg(book_path)
+        .output()
+        .map_err(|err| format!("Failed to run ansible-playbook: {}", err))?;
+    if output.status.success() {
+        return Ok(());
+    }
+    let mut errors = String::from_utf8_lossy(&output.stderr).into_owned();
+    if *verbose || errors.trim().is_empty() {
+        errors = format!("{}{}", String::from_utf8_lossy(&output.stdout), errors);
+    }
+    Err(errors)
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// The things that can be wrong with the playbooks, the playbook dir or the inventory the
+/// cli is pointed at
+#[derive(Debug)]
+pub enum Error {
+    /// The playbook dir does not exist or is not readable
+    PlaybookDirNotFound(PathBuf),
+    /// The playbook does not exist or is not readable
+    PlaybookNotFound(PathBuf),
+    /// There is no playbook with this file num in the playbook dir
+    IndexOutOfRange { index: u64, count: usize },
+    /// The playbook is not valid yaml, or does not fit the playbook model
+    Yaml { path: PathBuf, message: String },
+    /// The playbook is valid yaml, but not a list of plays
+    NotAPlaybook(PathBuf),
+    /// A `# key: value` header comment of the playbook is not valid
+    InvalidHeader {
+        path: PathBuf,
+        key: String,
+        message: String,
+    },
+    /// The inventory does not exist
+    InventoryMissing(PathBuf),
+}
+
+impl Error {
+    /// Gives the error for yaml that could not be parsed, with where it went wrong
+    pub fn yaml(path: &Path, err: serde_yaml::Error) -> Error {
+        Error::Yaml {
+            path: path.to_path_buf(),
+            message: err.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::PlaybookDirNotFound(path) => write!(
+                f,
+                "Playbook dir {} does not exist or is not readable",
+                path.display()
+            ),
+            Error::PlaybookNotFound(path) => write!(
+                f,
+                "File {} does not exist or is not readable",
+                path.display()
+            ),
+            Error::IndexOutOfRange { index, count } => write!(
+                f,
+                "There is no playbook {}, there are {} playbooks (0-{})",
+                index,
+                count,
+                count.saturating_sub(1)
+            ),
+            // serde_yaml already says where it went wrong in its message
+            Error::Yaml { path, message } => {
+                write!(f, "File {} is not parsable: {}", path.display(), message)
+            }
+            Error::NotAPlaybook(path) => {
+                write!(f, "File {} is not a list of plays", path.display())
+            }
+            Error::InvalidHeader { path, key, message } => write!(
+                f,
+                "File {} has an invalid # {}: header: {}",
+                path.display(),
+                key,
+                message
+            ),
+            Error::InventoryMissing(path) => {
+                write!(f, "Inventory {} does not exist", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -1,11 +1,23 @@
 use clap::{Args, Parser, Subcommand};
 use cli::commands::describe::call_describe;
+use cli::commands::history::call_history;
 use cli::commands::list::call_list;
-use cli::commands::run::call_run;
+use cli::commands::plan::{self, call_plan};
+use cli::commands::run::{self, call_run, RetryPolicy, RunOptions};
+use cli::commands::show::call_show;
+use cli::commands::validate::{self, call_validate};
 use cli::utilities::args;
+use cli::utilities::history;
+use cli::utilities::options::AnsibleOptions;
+use cli::utilities::prompt;
+use cli::utilities::report::{self, ReportFormat};
+use cli::utilities::vars;
 use dotenv::dotenv;
 use std::env;
+use std::io::{self, IsTerminal};
 use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -18,6 +30,8 @@ struct Cli {
     playbook_dir: PathBuf,
     #[arg(short, long, default_value = "inventory.yaml")]
     inventory: PathBuf,
+    #[arg(short, long, default_value = ".batch-ansible/")]
+    state_dir: PathBuf,
 }
 
 #[derive(Subcommand)]
@@ -25,9 +39,26 @@ enum Commands {
     #[command(name = "list", about = "This will list all the available playbooks")]
     List(ListArgs),
     #[command(name = "run", about = "This will run a specified playbook")]
-    Run(PlaybookArgs),
+    Run(Box<RunArgs>),
+    // A plan always runs in check mode with diffs, so `--check` and `--diff` do nothing there
+    #[command(
+        name = "plan",
+        about = "This will show what specified playbooks would change",
+        mut_arg("check", |arg| arg.hide(true)),
+        mut_arg("diff", |arg| arg.hide(true))
+    )]
+    Plan(Box<PlanArgs>),
     #[command(name = "describe", about = "This will describe a specified playbook")]
     Describe(PlaybookArgs),
+    #[command(
+        name = "validate",
+        about = "This will check specified (or all) playbooks"
+    )]
+    Validate(ValidateArgs),
+    #[command(name = "history", about = "This will list all the previous runs")]
+    History(ListArgs),
+    #[command(name = "show", about = "This will show a previous run")]
+    Show(ShowArgs),
 }
 
 #[derive(Args)]
@@ -36,10 +67,131 @@ struct ListArgs {}
 #[derive(Args)]
 struct PlaybookArgs {
     books: Vec<String>,
+    #[arg(long)]
+    vars_file: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    #[arg(required_unless_present = "failed_from")]
+    books: Vec<String>,
+    #[arg(long, conflicts_with = "books", value_name = "RUN_ID")]
+    failed_from: Option<String>,
+    #[arg(long, conflicts_with = "failed_from")]
+    vars_file: Vec<PathBuf>,
+    #[command(flatten)]
+    ansible: AnsibleOptions,
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+    #[arg(short, long, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+    #[arg(long, default_value_t = 0)]
+    retries: u32,
+    #[arg(long, value_parser = humantime::parse_duration, default_value = "30s")]
+    retry_delay: Duration,
+    #[arg(long, value_parser = run::parse_backoff, default_value_t = 1.0)]
+    retry_backoff: f64,
+    #[arg(long)]
+    fail_fast: bool,
+    #[arg(long)]
+    no_validate: bool,
+    #[arg(long)]
+    no_prompt: bool,
+    #[arg(long, conflicts_with = "no_prompt")]
+    save_answers: bool,
+    #[arg(long)]
+    structured: bool,
+    #[arg(long)]
+    prefix: bool,
+    #[arg(long)]
+    timestamps: bool,
+    #[arg(long, default_value = "logs/")]
+    log_dir: PathBuf,
+    #[arg(long, default_value_t = 20)]
+    tail: usize,
+    #[arg(long, value_enum, requires = "report_file")]
+    report: Option<ReportFormat>,
+    #[arg(long, requires = "report")]
+    report_file: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct PlanArgs {
+    #[arg(required = true)]
+    books: Vec<String>,
+    #[arg(long)]
+    vars_file: Vec<PathBuf>,
+    #[command(flatten)]
+    ansible: AnsibleOptions,
+    #[arg(short, long, default_value_t = 1)]
+    jobs: usize,
+    #[arg(short, long, value_parser = humantime::parse_duration)]
+    timeout: Option<Duration>,
+    #[arg(long)]
+    no_validate: bool,
+    #[arg(long, default_value = "logs/")]
+    log_dir: PathBuf,
+    #[arg(long, default_value_t = 20)]
+    tail: usize,
+}
+
+#[derive(Args)]
+struct ValidateArgs {
+    books: Vec<String>,
+    #[arg(long)]
+    syntax_check: bool,
+}
+
+#[derive(Args)]
+struct ShowArgs {
+    run_id: String,
+}
+
+/// Exit code when a playbook did not succeed
+const EXIT_PLAYBOOK_FAILED: i32 = 1;
+
+/// Exit code when the cli itself could not do what it was asked, e.g. a missing playbook dir
+const EXIT_TOOL_ERROR: i32 = 2;
+
+trait OrExit<T> {
+    /// Gives the value, or prints the error and exits with `EXIT_TOOL_ERROR`
+    fn or_exit(self, message: &str) -> T;
+}
+
+impl<T, E: std::fmt::Display> OrExit<T> for Result<T, E> {
+    fn or_exit(self, message: &str) -> T {
+        self.unwrap_or_else(|err| {
+            eprintln!("{}: {}", message, err);
+            process::exit(EXIT_TOOL_ERROR)
+        })
+    }
+}
+
+/// Exits with `EXIT_TOOL_ERROR` when a playbook could not be run at all, or else with
+/// `EXIT_PLAYBOOK_FAILED` when a playbook did not succeed
+fn exit_for(results: &[run::BookResult]) {
+    if results.iter().any(|result| result.tool_error) {
+        process::exit(EXIT_TOOL_ERROR);
+    }
+    if results
+        .iter()
+        .any(|result| result.status != run::Status::Success)
+    {
+        process::exit(EXIT_PLAYBOOK_FAILED);
+    }
+}
+
+/// Merges every `--vars-file`, later files win over earlier ones
+fn load_global_vars(files: &[PathBuf]) -> vars::ExtraVars {
+    let mut global_vars = vars::ExtraVars::new();
+    for file in files {
+        global_vars.extend(vars::load_vars_file(file).or_exit("Failed to load vars file"));
+    }
+    global_vars
 }
 
 fn main() {
-    dotenv().ok();
+    let env_file = dotenv().unwrap_or_else(|_| PathBuf::from(".env"));
 
     let mut cli = Cli::parse();
     if let Ok(playbook_dir) = env::var("PLAYBOOK_DIR") {
@@ -48,56 +200,196 @@ fn main() {
     if let Ok(inventory_dir) = env::var("INVENTORY_DIR") {
         cli.inventory = PathBuf::from(inventory_dir);
     }
+    if let Ok(state_dir) = env::var("STATE_DIR") {
+        cli.state_dir = PathBuf::from(state_dir);
+    }
     if let Ok(verbose) = env::var("VERBOSE") {
         cli.verbose = verbose.parse::<bool>().unwrap_or(false);
     }
 
     match &cli.command {
         Commands::List(_) => {
-            let mapped_files = args::map_files(&cli.playbook_dir).expect("Failed to map files");
+            let mapped_files = args::map_files(&cli.playbook_dir).or_exit("Failed to map files");
             print!(
                 "{}",
-                call_list(&cli.verbose, &mapped_files, &cli.playbook_dir).expect("Failed to list")
+                call_list(&cli.verbose, &mapped_files, &cli.playbook_dir).or_exit("Failed to list")
             )
         }
         Commands::Run(args) => {
-            let mapped_files =
-                args::arg_parse_env(&args.books, &cli.playbook_dir).expect("Failed to map files");
-            let mut appended_mapped_files = mapped_files.clone();
-            for (i, book_map) in mapped_files.iter() {
-                let book = book_map.0.clone();
-                let mut envs_map = book_map.1.clone();
-                if let Ok(envs) = env::var(book.clone()) {
-                    for env in envs.split(",") {
-                        let env_var: Vec<&str> = env.split("=").collect();
-                        if env_var.len() == 2 {
-                            envs_map.insert(env_var[0].to_string(), env_var[1].to_string());
-                        } else {
-                            panic!("Misformatted env var: {}", env);
+            let mut playbook_dir = cli.playbook_dir.clone();
+            let mut inventory = cli.inventory.clone();
+            let mut mapped_files = match &args.failed_from {
+                Some(run_id) => {
+                    let previous =
+                        history::load_run(&cli.state_dir, run_id).or_exit("Failed to load run");
+                    // A rerun runs against what the run it reruns ran against
+                    for (name, recorded, current) in [
+                        ("playbook dir", &previous.playbook_dir, &mut playbook_dir),
+                        ("inventory", &previous.inventory, &mut inventory),
+                    ] {
+                        if recorded != current {
+                            eprintln!(
+                                "Warning: using the {} of run {}, {}, instead of {}",
+                                name,
+                                previous.id,
+                                recorded.display(),
+                                current.display()
+                            );
+                            *current = recorded.clone();
                         }
                     }
+                    let mut books = history::failed_books(&previous, &playbook_dir)
+                        .or_exit("Failed to map files");
+                    for (_, _, book_options) in books.values_mut() {
+                        *book_options = args.ansible.merged(book_options);
+                    }
+                    books
+                }
+                None => {
+                    let global_vars = load_global_vars(&args.vars_file);
+                    args::arg_parse_env(&args.books, &playbook_dir, &global_vars, &args.ansible)
+                        .or_exit("Failed to map files")
+                }
+            };
+            if !args.no_validate && !args.no_prompt && io::stdin().is_terminal() {
+                let answers = prompt::prompt_missing(&mut mapped_files, &playbook_dir, &inventory)
+                    .or_exit("Failed to prompt for variables");
+                if args.save_answers && !answers.is_empty() {
+                    prompt::save_answers(&env_file, &answers).or_exit("Failed to save answers");
                 }
-
-                appended_mapped_files.insert(*i, (book, envs_map));
             }
-            print!(
-                "{}",
-                call_run(
-                    &appended_mapped_files,
-                    &cli.verbose,
-                    &cli.playbook_dir,
-                    &cli.inventory
-                )
-                .expect("Failed to run")
+            let (run_id, timestamp) =
+                history::new_run(&cli.state_dir).or_exit("Failed to start run");
+            let results = call_run(
+                &mapped_files,
+                &cli.verbose,
+                &RunOptions {
+                    validate: !args.no_validate,
+                    jobs: args.jobs,
+                    structured: args.structured,
+                    prefix: args.prefix,
+                    timestamps: args.timestamps,
+                    log_dir: args.log_dir.join(&run_id),
+                    fail_fast: args.fail_fast,
+                    timeout: args.timeout,
+                    retry: RetryPolicy {
+                        retries: args.retries,
+                        delay: args.retry_delay,
+                        backoff: args.retry_backoff,
+                    },
+                },
+                &playbook_dir,
+                &inventory,
+            )
+            .inspect_err(|_| history::discard_run(&cli.state_dir, &run_id))
+            .or_exit("Failed to run");
+            print!("{}", run::summary(&results, &args.tail));
+            if let (Some(format), Some(report_file)) = (&args.report, &args.report_file) {
+                report::write_report(&results, format, report_file)
+                    .or_exit("Failed to write report");
+            }
+            history::save_run(
+                &cli.state_dir,
+                &run_id,
+                history::RunKind::Run,
+                &timestamp,
+                &results,
+                &playbook_dir,
+                &inventory,
             )
+            .or_exit("Failed to save run");
+            println!("Run id: {}", run_id);
+            exit_for(&results);
         }
-        Commands::Describe(args) => {
+        Commands::Plan(args) => {
+            let global_vars = load_global_vars(&args.vars_file);
             let mapped_files =
-                args::arg_parse(&args.books, &cli.playbook_dir).expect("Failed to map files");
+                args::arg_parse_env(&args.books, &cli.playbook_dir, &global_vars, &args.ansible)
+                    .or_exit("Failed to map files");
+            let (run_id, timestamp) =
+                history::new_run(&cli.state_dir).or_exit("Failed to start run");
+            let results = call_plan(
+                &mapped_files,
+                &cli.verbose,
+                &RunOptions {
+                    validate: !args.no_validate,
+                    jobs: args.jobs,
+                    structured: true,
+                    prefix: false,
+                    timestamps: false,
+                    log_dir: args.log_dir.join(&run_id),
+                    fail_fast: false,
+                    timeout: args.timeout,
+                    retry: RetryPolicy {
+                        retries: 0,
+                        delay: Duration::ZERO,
+                        backoff: 1.0,
+                    },
+                },
+                &cli.playbook_dir,
+                &cli.inventory,
+            )
+            .inspect_err(|_| history::discard_run(&cli.state_dir, &run_id))
+            .or_exit("Failed to plan");
+            print!("{}", plan::plan_summary(&results, &args.tail));
+            history::save_run(
+                &cli.state_dir,
+                &run_id,
+                history::RunKind::Plan,
+                &timestamp,
+                &results,
+                &cli.playbook_dir,
+                &cli.inventory,
+            )
+            .or_exit("Failed to save run");
+            println!("Run id: {}", run_id);
+            exit_for(&results);
+        }
+        Commands::Describe(args) => {
+            let global_vars = load_global_vars(&args.vars_file);
+            let mapped_files = args::arg_parse_env(
+                &args.books,
+                &cli.playbook_dir,
+                &global_vars,
+                &AnsibleOptions::default(),
+            )
+            .or_exit("Failed to map files");
             print!(
                 "{}",
                 call_describe(&mapped_files, &cli.verbose, &cli.playbook_dir)
-                    .expect("Failed to describe")
+                    .or_exit("Failed to describe")
+            )
+        }
+        Commands::Validate(args) => {
+            let mapped_files = if args.books.is_empty() {
+                args::map_files(&cli.playbook_dir)
+            } else {
+                args::arg_parse(&args.books, &cli.playbook_dir)
+            }
+            .or_exit("Failed to map files");
+            let results = call_validate(
+                &mapped_files,
+                args.syntax_check,
+                &cli.verbose,
+                &cli.playbook_dir,
+                &cli.inventory,
+            )
+            .or_exit("Failed to validate");
+            print!("{}", validate::validation_table(&results));
+            if !results.iter().all(|result| result.passed()) {
+                process::exit(EXIT_PLAYBOOK_FAILED);
+            }
+        }
+        Commands::History(_) => {
+            print!(
+                "{}",
+                call_history(&cli.verbose, &cli.state_dir).or_exit("Failed to list history")
+            )
+        }
+        Commands::Show(args) => {
+            print!(
+                "{}",
+                call_show(&args.run_id, &cli.verbose, &cli.state_dir).or_exit("Failed to show run")
             )
         }
     }
@@ -1,7 +1,12 @@
+use crate::error::Error;
+use crate::utilities::options::AnsibleOptions;
+use crate::utilities::vars::{self, ExtraVars};
 use std::collections::HashMap as Map;
 use std::fs;
-use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+
+/// A map of file nums to the playbook file name, its extra vars and its ansible options
+pub type BookEnvs = Map<u64, (String, ExtraVars, AnsibleOptions)>;
 
 /// Implements the map files function, which takes a directory path
 /// and returns a map of alphabetical order to file name.
@@ -11,17 +16,21 @@ use std::path::PathBuf;
 ///
 /// # Errors
 /// Returns an error if the directory cannot be read.
-pub fn map_files(path: &PathBuf) -> Result<Map<u64, String>, Box<dyn std::error::Error>> {
+pub fn map_files(path: &Path) -> Result<Map<u64, String>, Error> {
     let mut map = Map::new();
-    let mut files = fs::read_dir(path)?
-        .map(|res| res.map(|e| e.file_name()))
-        .collect::<Result<Vec<_>, io::Error>>()?;
+    let mut files = fs::read_dir(path)
+        .and_then(|entries| {
+            entries
+                .map(|res| res.map(|e| e.file_name()))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .map_err(|_| Error::PlaybookDirNotFound(path.to_path_buf()))?;
 
-    files.retain(|f| f.to_str().unwrap().ends_with(".yaml"));
+    files.retain(|f| f.to_str().is_some_and(|f| f.ends_with(".yaml")));
     files.sort();
 
     for (i, file) in files.iter().enumerate() {
-        map.insert(i as u64, file.to_str().unwrap().to_string());
+        map.insert(i as u64, file.to_string_lossy().to_string());
     }
 
     Ok(map)
@@ -37,10 +46,15 @@ pub fn map_files(path: &PathBuf) -> Result<Map<u64, String>, Box<dyn std::error:
 /// # Errors
 /// Returns an error if the directory cannot be read.
 /// Returns an error if the file number does not exist.
-pub fn map_name(file_num: &u64, path: &PathBuf) -> Result<String, Box<dyn std::error::Error>> {
+pub fn map_name(file_num: &u64, path: &Path) -> Result<String, Error> {
     let files = map_files(path)?;
-    let file_name = files.get(file_num).unwrap();
-    Ok(file_name.to_string())
+    match files.get(file_num) {
+        Some(file_name) => Ok(file_name.to_string()),
+        None => Err(Error::IndexOutOfRange {
+            index: *file_num,
+            count: files.len(),
+        }),
+    }
 }
 
 /// Implements the map_num function which takes a file name and uses map_files to match it to a num
@@ -52,10 +66,12 @@ pub fn map_name(file_num: &u64, path: &PathBuf) -> Result<String, Box<dyn std::e
 /// # Errors
 /// Returns an error if the directory cannot be read.
 /// Returns an error if the file name does not exist.
-pub fn map_num(file_name: &str, path: &PathBuf) -> Result<u64, Box<dyn std::error::Error>> {
+pub fn map_num(file_name: &str, path: &Path) -> Result<u64, Error> {
     let files = map_files(path)?;
-    let file_num = files.iter().find(|(_, name)| name == &file_name).unwrap().0;
-    Ok(*file_num)
+    match files.iter().find(|(_, name)| name == &file_name) {
+        Some((file_num, _)) => Ok(*file_num),
+        None => Err(Error::PlaybookNotFound(path.join(file_name))),
+    }
 }
 
 /// Implements the arg_parse function which takes either a vector of file nums, or file names and parses them
@@ -71,10 +87,7 @@ pub fn map_num(file_name: &str, path: &PathBuf) -> Result<u64, Box<dyn std::erro
 /// Returns an error if the directory cannot be read.
 /// Returns an error if the file name does not exist.
 /// Returns an error if the file number does not exist.
-pub fn arg_parse(
-    args: &Vec<String>,
-    path: &PathBuf,
-) -> Result<Map<u64, String>, Box<dyn std::error::Error>> {
+pub fn arg_parse(args: &Vec<String>, path: &Path) -> Result<Map<u64, String>, Error> {
     let mut map = Map::new();
     let mut names = Vec::new();
     let mut nums = Vec::new();
@@ -82,20 +95,19 @@ pub fn arg_parse(
     for arg in args {
         if arg.contains("-") {
             let range: Vec<&str> = arg.split("-").collect();
-            if range.len() == 2
-                && range[0].parse::<u64>().is_ok()
-                && range[1].parse::<u64>().is_ok()
+            if let [Ok(start), Ok(end)] = range
+                .iter()
+                .map(|num| num.parse::<u64>())
+                .collect::<Vec<_>>()[..]
             {
-                let start = range[0].parse::<u64>()?;
-                let end = range[1].parse::<u64>()?;
                 for i in start..=end {
                     nums.push(i);
                 }
             } else {
                 names.push(arg.to_string());
             }
-        } else if arg.parse::<u64>().is_ok() {
-            nums.push(arg.parse::<u64>()?);
+        } else if let Ok(num) = arg.parse::<u64>() {
+            nums.push(num);
         } else {
             names.push(arg.to_string());
         }
@@ -114,48 +126,59 @@ pub fn arg_parse(
 }
 
 /// Implements the arg_parse_env function which takes a lists of comma seperated items, where
-/// the first value is the playbook, and the rest are environment variables.
-/// it returns file nums as a primary key, file names as a secondary key, and a vector of environment variables.
+/// the first value is the playbook, and the rest are environment variables, `@file` vars files
+/// or `--option` ansible options.
+/// it returns file nums as a primary key, file names as a secondary key, a map of typed extra vars
+/// and the ansible options.
+///
+/// e.g. `test.yaml,@vars/prod.yaml,print_msg=hi,count=3,hosts=[a, b],msg="a, b",--limit=web`, see
+/// `vars::parse_vars` for how values are typed and escaped, `vars::book_vars` for how
+/// `global_vars`, vars files, env variables and inline vars are merged, and
+/// `AnsibleOptions::parse_option` for the options
 ///
 /// # Arguments
 /// * `args` - A vector of strings corresponding to file names or file numbers and env vars
 /// * `path` - A path to a directory.
+/// * `global_vars` - The extra vars every playbook gets
+/// * `global_options` - The ansible options every playbook gets, unless it sets its own
 ///
 /// # Errors
 /// Returns an error if the directory cannot be read.
 /// Returns an error if the file name does not exist.
 /// Returns an error if the file number does not exist.
 /// Returns an error if the env var is misformatted.
+/// Returns an error if a vars file cannot be read.
+/// Returns an error if an ansible option is not known or misformatted.
 pub fn arg_parse_env(
     args: &Vec<String>,
-    path: &PathBuf,
-) -> Result<Map<u64, (String, Map<String, String>)>, Box<dyn std::error::Error>> {
+    path: &Path,
+    global_vars: &ExtraVars,
+    global_options: &AnsibleOptions,
+) -> Result<BookEnvs, Box<dyn std::error::Error>> {
     let mut map = Map::new();
     for arg in args {
-        if arg.contains(",") {
-            let env_vars: Vec<&str> = arg.split(",").collect();
-            if env_vars.len() > 1 {
-                let playbook = env_vars[0];
-                let mut env_map = Map::new();
-                for env_var in env_vars.iter().skip(1) {
-                    let env_var: Vec<&str> = env_var.split("=").collect();
-                    if env_var.len() == 2 {
-                        env_map.insert(env_var[0].to_string(), env_var[1].to_string());
-                    } else {
-                        return Err("Environment variable must be in the format KEY=VALUE".into());
-                    }
-                }
-                let playbooks = arg_parse(&vec![playbook.to_string()], path)?;
-                for (num, name) in playbooks {
-                    map.insert(num, (name, env_map.clone()));
-                }
-            }
-        } else {
-            let playbooks = arg_parse(&vec![arg.to_string()], path)?;
-            for (num, name) in playbooks {
-                map.insert(num, (name, Map::new()));
+        let mut parts = vars::split_unquoted(arg, ',').into_iter();
+        let Some(playbook) = parts.next() else {
+            continue;
+        };
+        let mut files = Vec::new();
+        let mut env_map = Map::new();
+        let mut options = AnsibleOptions::default();
+        for env_var in parts {
+            if let Some(file) = env_var.trim().strip_prefix('@') {
+                files.push(PathBuf::from(file));
+            } else if env_var.trim().starts_with("--") {
+                options.parse_option(&env_var)?;
+            } else {
+                let (key, value) = vars::parse_pair(&env_var)?;
+                env_map.insert(key, value);
             }
         }
+        let playbooks = arg_parse(&vec![playbook], path)?;
+        for (num, name) in playbooks {
+            let book_vars = vars::book_vars(&name, global_vars, &files, &env_map)?;
+            map.insert(num, (name, book_vars, global_options.merged(&options)));
+        }
     }
     Ok(map)
 }
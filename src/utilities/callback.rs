@@ -0,0 +1,207 @@
+use crate::utilities::diff;
+use crate::utilities::recap::HostStats;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap as Map;
+
+/// The environment variable that picks the stdout callback of `ansible-playbook`
+pub const STDOUT_CALLBACK: (&str, &str) = ("ANSIBLE_STDOUT_CALLBACK", "json");
+
+/// The document ansible's json stdout callback prints once a playbook is done
+#[derive(Deserialize)]
+pub struct PlaybookOutput {
+    #[serde(default)]
+    pub plays: Vec<PlayOutput>,
+    #[serde(default)]
+    pub stats: Map<String, CallbackStats>,
+}
+
+/// A single play of a `PlaybookOutput`
+#[derive(Deserialize)]
+pub struct PlayOutput {
+    pub play: Named,
+    #[serde(default)]
+    pub tasks: Vec<TaskOutput>,
+}
+
+/// A single task of a `PlayOutput`, with the result for every host it ran on
+#[derive(Deserialize)]
+pub struct TaskOutput {
+    pub task: Named,
+    #[serde(default)]
+    pub hosts: Map<String, HostResult>,
+}
+
+/// The part of a play or task ansible describes it with
+#[derive(Deserialize)]
+pub struct Named {
+    #[serde(default)]
+    pub name: String,
+}
+
+/// What a task did on a single host
+#[derive(Deserialize)]
+pub struct HostResult {
+    #[serde(default)]
+    pub action: Option<String>,
+    #[serde(default)]
+    pub changed: bool,
+    #[serde(default)]
+    pub failed: bool,
+    #[serde(default)]
+    pub skipped: bool,
+    #[serde(default)]
+    pub unreachable: bool,
+    #[serde(default)]
+    pub msg: Option<serde_json::Value>,
+    /// What the task changed, when ansible was run with `--diff`
+    #[serde(default)]
+    pub diff: Option<serde_json::Value>,
+    /// The result of every item of a task with a loop, each of which can hold a `diff`
+    #[serde(default)]
+    pub results: Option<serde_json::Value>,
+}
+
+/// The counts the json callback gives for a single host once the playbook is done
+#[derive(Deserialize)]
+pub struct CallbackStats {
+    #[serde(default)]
+    pub ok: u64,
+    #[serde(default)]
+    pub changed: u64,
+    #[serde(default)]
+    pub unreachable: u64,
+    #[serde(default)]
+    pub failures: u64,
+    #[serde(default)]
+    pub skipped: u64,
+    #[serde(default)]
+    pub rescued: u64,
+    #[serde(default)]
+    pub ignored: u64,
+}
+
+/// What a single task did on a single host, flattened out of a `PlaybookOutput`
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TaskResult {
+    pub play: String,
+    pub task: String,
+    pub host: String,
+    pub action: Option<String>,
+    pub changed: bool,
+    pub failed: bool,
+    pub skipped: bool,
+    pub unreachable: bool,
+    pub msg: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub diff: Option<String>,
+}
+
+/// Implements the parse output function, which reads the stdout of `ansible-playbook` run
+/// with the json stdout callback. Anything printed before the document, e.g. warnings,
+/// is ignored
+///
+/// # Errors
+/// Returns an error if there is no json document in the output
+pub fn parse_output(stdout: &str) -> Result<PlaybookOutput, Box<dyn std::error::Error>> {
+    let start = stdout.find('{').ok_or("No json callback output found")?;
+    let end = stdout.rfind('}').ok_or("No json callback output found")?;
+    Ok(serde_json::from_str(&stdout[start..=end])?)
+}
+
+impl PlaybookOutput {
+    /// Gives every task result of every play, in the order ansible ran them
+    pub fn task_results(&self) -> Vec<TaskResult> {
+        let mut results = Vec::new();
+        for play in &self.plays {
+            for task in &play.tasks {
+                let mut hosts: Vec<_> = task.hosts.iter().collect();
+                hosts.sort_by(|a, b| a.0.cmp(b.0));
+                for (host, result) in hosts {
+                    results.push(TaskResult {
+                        play: play.play.name.clone(),
+                        task: task.task.name.clone(),
+                        host: host.clone(),
+                        action: result.action.clone(),
+                        changed: result.changed,
+                        failed: result.failed,
+                        skipped: result.skipped,
+                        unreachable: result.unreachable,
+                        msg: result.msg.as_ref().map(|msg| match msg {
+                            serde_json::Value::String(msg) => msg.clone(),
+                            msg => msg.to_string(),
+                        }),
+                        diff: result.rendered_diff(),
+                    });
+                }
+            }
+        }
+        results
+    }
+
+    /// Gives the final counts of every host, the same as the PLAY RECAP would
+    pub fn host_stats(&self) -> Vec<HostStats> {
+        let mut hosts: Vec<HostStats> = self
+            .stats
+            .iter()
+            .map(|(host, stats)| HostStats {
+                host: host.clone(),
+                ok: stats.ok,
+                changed: stats.changed,
+                unreachable: stats.unreachable,
+                failed: stats.failures,
+                skipped: stats.skipped,
+                rescued: stats.rescued,
+                ignored: stats.ignored,
+            })
+            .collect();
+        hosts.sort_by(|a, b| a.host.cmp(&b.host));
+        hosts
+    }
+}
+
+impl HostResult {
+    /// Gives the diff of the task on this host, including the diffs of every loop item, or
+    /// None if it did not give any
+    pub fn rendered_diff(&self) -> Option<String> {
+        let mut rendered = "".to_owned();
+        if let Some(task_diff) = &self.diff {
+            rendered.push_str(&diff::render_diff(task_diff));
+        }
+        if let Some(serde_json::Value::Array(items)) = &self.results {
+            for item in items {
+                if let Some(item_diff) = item.get("diff") {
+                    rendered.push_str(&diff::render_diff(item_diff));
+                }
+            }
+        }
+        if rendered.is_empty() {
+            None
+        } else {
+            Some(rendered)
+        }
+    }
+}
+
+/// Implements the task failures function, which gives a line for every task that failed or
+/// could not reach its host, with every line indented by `indent`
+pub fn task_failures(tasks: &[TaskResult], indent: &str) -> String {
+    let mut lines = "".to_owned();
+    for task in tasks {
+        let kind = if task.unreachable {
+            "Unreachable"
+        } else if task.failed {
+            "Failed"
+        } else {
+            continue;
+        };
+        lines.push_str(&format!(
+            "{}{}: {} / {} on {}",
+            indent, kind, task.play, task.task, task.host
+        ));
+        if let Some(msg) = &task.msg {
+            lines.push_str(&format!(": {}", msg));
+        }
+        lines.push('\n');
+    }
+    lines
+}
@@ -0,0 +1,172 @@
+use crate::utilities::parse;
+use crate::utilities::BookEnvs;
+use std::collections::HashMap as Map;
+use std::path::Path;
+
+/// Implements the dependency graph function, which reads the `# depends_on:` header of every
+/// selected playbook and returns a map of file nums to the file nums they depend on.
+///
+/// Dependencies on playbooks that are not part of the batch are ignored, they only decide
+/// the order of the playbooks that were selected.
+///
+/// # Errors
+/// Returns an error if a playbook cannot be read
+/// Returns an error if the dependencies contain a cycle
+pub fn dependency_graph(
+    books: &BookEnvs,
+    playbook_dir: &Path,
+) -> Result<Map<u64, Vec<u64>>, Box<dyn std::error::Error>> {
+    let nums: Map<&str, u64> = books
+        .iter()
+        .map(|(i, (book, _, _))| (book.as_str(), *i))
+        .collect();
+
+    let mut graph = Map::new();
+    for (i, (book, _, _)) in books {
+        let mut deps: Vec<u64> = parse::unwrap_deps(&playbook_dir.join(book))?
+            .iter()
+            .filter_map(|dep| nums.get(dep.as_str()).copied())
+            .collect();
+        deps.sort();
+        deps.dedup();
+        graph.insert(*i, deps);
+    }
+
+    if let Some(cycle) = find_cycle(&graph) {
+        let names: Vec<&str> = cycle.iter().map(|i| books[i].0.as_str()).collect();
+        return Err(format!("Dependency cycle between playbooks: {}", names.join(" -> ")).into());
+    }
+    Ok(graph)
+}
+
+/// Implements the find cycle function, which returns the file nums that make up a cycle
+/// in the graph, if there is one
+fn find_cycle(graph: &Map<u64, Vec<u64>>) -> Option<Vec<u64>> {
+    let mut starts: Vec<&u64> = graph.keys().collect();
+    starts.sort();
+    let mut done = Vec::new();
+    for start in starts {
+        let mut path = Vec::new();
+        if let Some(cycle) = visit(*start, graph, &mut path, &mut done) {
+            return Some(cycle);
+        }
+    }
+    None
+}
+
+/// Depth first walk used by `find_cycle`
+fn visit(
+    node: u64,
+    graph: &Map<u64, Vec<u64>>,
+    path: &mut Vec<u64>,
+    done: &mut Vec<u64>,
+) -> Option<Vec<u64>> {
+    if done.contains(&node) {
+        return None;
+    }
+    if let Some(start) = path.iter().position(|i| *i == node) {
+        let mut cycle = path[start..].to_vec();
+        cycle.push(node);
+        return Some(cycle);
+    }
+    path.push(node);
+    for dep in &graph[&node] {
+        if let Some(cycle) = visit(*dep, graph, path, done) {
+            return Some(cycle);
+        }
+    }
+    path.pop();
+    done.push(node);
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utilities::options::AnsibleOptions;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn graph(edges: &[(u64, &[u64])]) -> Map<u64, Vec<u64>> {
+        edges
+            .iter()
+            .map(|(node, deps)| (*node, deps.to_vec()))
+            .collect()
+    }
+
+    /// Writes the playbooks to a fresh dir, each with its header, and selects all of them
+    fn playbooks(name: &str, headers: &[(&str, &str)]) -> (PathBuf, BookEnvs) {
+        let dir = std::env::temp_dir().join(format!("cli-deps-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let mut books = BookEnvs::new();
+        for (i, (book, header)) in headers.iter().enumerate() {
+            let contents = format!("{}\n- hosts: all\n  tasks: []\n", header);
+            fs::write(dir.join(book), contents).unwrap();
+            books.insert(
+                i as u64,
+                (
+                    book.to_string(),
+                    Default::default(),
+                    AnsibleOptions::default(),
+                ),
+            );
+        }
+        (dir, books)
+    }
+
+    #[test]
+    fn find_cycle_gives_none_without_a_cycle() {
+        assert_eq!(
+            find_cycle(&graph(&[(0, &[]), (1, &[0]), (2, &[0, 1])])),
+            None
+        );
+    }
+
+    #[test]
+    fn find_cycle_gives_the_nodes_of_a_cycle() {
+        assert_eq!(
+            find_cycle(&graph(&[(0, &[2]), (1, &[0]), (2, &[1])])),
+            Some(vec![0, 2, 1, 0])
+        );
+    }
+
+    #[test]
+    fn find_cycle_finds_a_self_dependency() {
+        assert_eq!(find_cycle(&graph(&[(0, &[]), (1, &[1])])), Some(vec![1, 1]));
+    }
+
+    #[test]
+    fn dependency_graph_reads_headers_and_ignores_books_outside_the_batch() {
+        let (dir, books) = playbooks(
+            "graph",
+            &[
+                ("a.yaml", ""),
+                ("b.yaml", "# depends_on: a.yaml, missing.yaml"),
+                ("c.yaml", "---\n# depends_on: b.yaml\n# depends_on: a.yaml,"),
+            ],
+        );
+        let graph = dependency_graph(&books, &dir).unwrap();
+        assert_eq!(graph[&0], Vec::<u64>::new());
+        assert_eq!(graph[&1], vec![0]);
+        assert_eq!(graph[&2], vec![0, 1]);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dependency_graph_refuses_a_cycle() {
+        let (dir, books) = playbooks(
+            "cycle",
+            &[
+                ("a.yaml", "# depends_on: b.yaml"),
+                ("b.yaml", "# depends_on: a.yaml"),
+            ],
+        );
+        let err = dependency_graph(&books, &dir).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Dependency cycle between playbooks: a.yaml -> b.yaml -> a.yaml"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
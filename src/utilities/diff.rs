@@ -0,0 +1,199 @@
+use serde_json::Value;
+
+/// How many unchanged lines are kept around every change
+const CONTEXT: usize = 3;
+
+/// Above this many line pairs, a diff just removes all the old lines and adds all the new ones
+const MAX_CELLS: usize = 4_000_000;
+
+/// A single line of a line diff
+enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+/// Implements the render diff function, which gives the `diff` a task returned in ansible's
+/// json callback output as unified diff style text. The `diff` is either a single
+/// `{before, after, before_header, after_header, prepared}` mapping or a list of them, where
+/// `prepared` is a diff ansible already rendered. Before and after values that are not text,
+/// e.g. the state of a file, are compared as yaml
+pub fn render_diff(diff: &Value) -> String {
+    let mut rendered = "".to_owned();
+    match diff {
+        Value::Array(diffs) => {
+            for diff in diffs {
+                rendered.push_str(&render_diff(diff));
+            }
+        }
+        Value::Object(fields) => {
+            if let Some(Value::String(prepared)) = fields.get("prepared") {
+                rendered.push_str(prepared.trim_end());
+                rendered.push('\n');
+            }
+            if fields.contains_key("before") || fields.contains_key("after") {
+                let before = diff_text(fields.get("before"));
+                let after = diff_text(fields.get("after"));
+                if before != after {
+                    rendered.push_str(&format!(
+                        "--- {}\n+++ {}\n",
+                        diff_header(fields.get("before_header"), "before"),
+                        diff_header(fields.get("after_header"), "after")
+                    ));
+                    for line in line_diff(&before, &after) {
+                        rendered.push_str(&line);
+                        rendered.push('\n');
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+    rendered
+}
+
+/// Implements the line diff function, which gives the lines that were removed (`-`) and added
+/// (`+`) to turn `before` into `after`, with up to `CONTEXT` unchanged lines (` `) around
+/// every change. Unchanged lines that are left out are replaced by a single `...`
+pub fn line_diff(before: &str, after: &str) -> Vec<String> {
+    let before: Vec<&str> = before.lines().collect();
+    let after: Vec<&str> = after.lines().collect();
+    let lines = diff_lines(&before, &after);
+
+    let changed: Vec<bool> = lines
+        .iter()
+        .map(|line| !matches!(line, Line::Same(_)))
+        .collect();
+    let mut output = Vec::new();
+    let mut skipped = false;
+    for (i, line) in lines.iter().enumerate() {
+        let start = i.saturating_sub(CONTEXT);
+        let end = (i + CONTEXT + 1).min(lines.len());
+        if !changed[start..end].contains(&true) {
+            skipped = true;
+            continue;
+        }
+        if skipped {
+            output.push("...".to_owned());
+            skipped = false;
+        }
+        output.push(match line {
+            Line::Same(text) => format!(" {}", text),
+            Line::Removed(text) => format!("-{}", text),
+            Line::Added(text) => format!("+{}", text),
+        });
+    }
+    output
+}
+
+/// Gives every line of both sides in order, using the longest common subsequence of lines
+fn diff_lines<'a>(before: &[&'a str], after: &[&'a str]) -> Vec<Line<'a>> {
+    if before.len().saturating_mul(after.len()) > MAX_CELLS {
+        return before
+            .iter()
+            .map(|line| Line::Removed(line))
+            .chain(after.iter().map(|line| Line::Added(line)))
+            .collect();
+    }
+
+    // common[i][j] is the length of the longest common subsequence of before[i..] and after[j..]
+    let mut common = vec![vec![0u32; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i][j] = if before[i] == after[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() && j < after.len() {
+        if before[i] == after[j] {
+            lines.push(Line::Same(before[i]));
+            i += 1;
+            j += 1;
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            lines.push(Line::Removed(before[i]));
+            i += 1;
+        } else {
+            lines.push(Line::Added(after[j]));
+            j += 1;
+        }
+    }
+    lines.extend(before[i..].iter().map(|line| Line::Removed(line)));
+    lines.extend(after[j..].iter().map(|line| Line::Added(line)));
+    lines
+}
+
+/// Gives one side of a diff as text
+fn diff_text(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(text)) => text.clone(),
+        Some(value) => serde_yaml::to_string(value).unwrap_or_else(|_| value.to_string()),
+    }
+}
+
+/// Gives the header of one side of a diff, or `default` if ansible did not give one
+fn diff_header(value: Option<&Value>, default: &str) -> String {
+    match value {
+        Some(Value::String(header)) if !header.is_empty() => header.clone(),
+        _ => default.to_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_diff_of_equal_text_is_empty() {
+        assert!(line_diff("a\nb\n", "a\nb\n").is_empty());
+    }
+
+    #[test]
+    fn line_diff_marks_removed_and_added_lines() {
+        assert_eq!(
+            line_diff("a\nb\nc\n", "a\nx\nc\n"),
+            vec![" a", "-b", "+x", " c"]
+        );
+    }
+
+    #[test]
+    fn line_diff_of_new_text_adds_every_line() {
+        assert_eq!(line_diff("", "a\nb"), vec!["+a", "+b"]);
+        assert_eq!(line_diff("a\nb", ""), vec!["-a", "-b"]);
+    }
+
+    #[test]
+    fn line_diff_keeps_context_around_changes() {
+        let before = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n";
+        let after = "1\n2\n3\n4\n5\n6\n7\n8\n9\nten\n";
+        assert_eq!(
+            line_diff(before, after),
+            vec!["...", " 7", " 8", " 9", "-10", "+ten"]
+        );
+    }
+
+    #[test]
+    fn line_diff_marks_skipped_lines_between_changes() {
+        let before = "a\n1\n2\n3\n4\n5\n6\n7\n8\nb\n";
+        let after = "A\n1\n2\n3\n4\n5\n6\n7\n8\nB\n";
+        assert_eq!(
+            line_diff(before, after),
+            vec!["-a", "+A", " 1", " 2", " 3", "...", " 6", " 7", " 8", "-b", "+B"]
+        );
+    }
+
+    #[test]
+    fn line_diff_of_huge_text_replaces_every_line() {
+        let before = "a\n".repeat(2001) + "b\n";
+        let after = "a\n".repeat(2001) + "c\n";
+        let lines = line_diff(&before, &after);
+        assert_eq!(lines.len(), 2002 * 2);
+        assert!(lines.iter().all(|line| !line.starts_with(' ')));
+    }
+}
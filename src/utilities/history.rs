@@ -0,0 +1,244 @@
+use crate::commands::run::{BookResult, Status};
+use crate::utilities::args;
+use crate::utilities::callback::TaskResult;
+use crate::utilities::options::AnsibleOptions;
+use crate::utilities::recap::HostStats;
+use crate::utilities::vars::{self, ExtraVars};
+use crate::utilities::BookEnvs;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap as Map;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Whether a saved run ran its playbooks, or only checked what they would change
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunKind {
+    #[default]
+    Run,
+    /// A `plan`, which runs every playbook with `--check --diff`
+    Plan,
+}
+
+impl std::fmt::Display for RunKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RunKind::Run => write!(f, "Run"),
+            RunKind::Plan => write!(f, "Plan"),
+        }
+    }
+}
+
+/// A batch run that was saved to the state directory
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub id: String,
+    #[serde(default)]
+    pub kind: RunKind,
+    pub timestamp: String,
+    pub playbook_dir: PathBuf,
+    pub inventory: PathBuf,
+    pub books: Vec<BookRecord>,
+}
+
+/// A single playbook of a saved run
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BookRecord {
+    pub index: u64,
+    pub book: String,
+    pub extra_vars: ExtraVars,
+    #[serde(default)]
+    pub options: AnsibleOptions,
+    pub status: Status,
+    #[serde(default)]
+    pub attempt: u32,
+    pub exit_code: Option<i32>,
+    pub duration_secs: f64,
+    pub log: Option<PathBuf>,
+    #[serde(default)]
+    pub hosts: Vec<HostStats>,
+    #[serde(default)]
+    pub tasks: Vec<TaskResult>,
+}
+
+/// Implements the new run function, which picks an id for a run that is about to start and
+/// reserves `<state_dir>/runs/<run-id>/` for it. Gives the id and the time the run started
+///
+/// # Errors
+/// Returns an error if the state directory cannot be written
+pub fn new_run(state_dir: &Path) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now()).to_string();
+    let base_id: String = timestamp
+        .chars()
+        .filter(|c| !matches!(c, '-' | ':'))
+        .collect();
+
+    let runs_dir = state_dir.join("runs");
+    fs::create_dir_all(&runs_dir)?;
+    let mut id = base_id.clone();
+    let mut attempt = 1;
+    // Creating the dir is what reserves the id, so two runs that start at the same time never
+    // get the same one
+    loop {
+        match fs::create_dir(runs_dir.join(&id)) {
+            Ok(()) => return Ok((id, timestamp)),
+            Err(err) if err.kind() == ErrorKind::AlreadyExists => {
+                id = format!("{}-{}", base_id, attempt);
+                attempt += 1;
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Implements the discard run function, which gives back the id of a run started with
+/// `new_run` that never ran, e.g. because validation refused it, by removing its empty
+/// `<state_dir>/runs/<run-id>/`. A dir that has anything in it is left alone
+pub fn discard_run(state_dir: &Path, id: &str) {
+    let _ = fs::remove_dir(state_dir.join("runs").join(id));
+}
+
+/// Implements the save run function, which stores the results of a run started with
+/// `new_run` as `<state_dir>/runs/<run-id>/run.json`
+///
+/// # Errors
+/// Returns an error if the state directory cannot be written
+pub fn save_run(
+    state_dir: &Path,
+    id: &str,
+    kind: RunKind,
+    timestamp: &str,
+    results: &[BookResult],
+    playbook_dir: &Path,
+    inventory: &Path,
+) -> Result<RunRecord, Box<dyn std::error::Error>> {
+    let mut books = Vec::new();
+    for result in results {
+        books.push(BookRecord {
+            index: result.index,
+            book: result.book.clone(),
+            extra_vars: vars::redact(&result.extra_vars),
+            options: result.options.clone(),
+            status: result.status,
+            attempt: result.attempt,
+            exit_code: result.exit_code,
+            duration_secs: result.duration().as_secs_f64(),
+            log: result.log.clone(),
+            hosts: result.hosts.clone(),
+            tasks: result.tasks.clone(),
+        });
+    }
+
+    let record = RunRecord {
+        id: id.to_owned(),
+        kind,
+        timestamp: timestamp.to_owned(),
+        playbook_dir: playbook_dir.to_path_buf(),
+        inventory: inventory.to_path_buf(),
+        books,
+    };
+    fs::write(
+        state_dir.join("runs").join(id).join("run.json"),
+        serde_json::to_string_pretty(&record)?,
+    )?;
+    Ok(record)
+}
+
+/// Implements the list runs function, which gives every saved run, oldest first. Runs that
+/// started in the same second are in the order of the number after their id, e.g.
+/// `20240101T000000Z`, `20240101T000000Z-2`, `20240101T000000Z-10`
+///
+/// # Errors
+/// Returns an error if the state directory cannot be read
+/// Returns an error if a run record is not parsable
+pub fn list_runs(state_dir: &Path) -> Result<Vec<RunRecord>, Box<dyn std::error::Error>> {
+    let runs_dir = state_dir.join("runs");
+    if !runs_dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut runs = Vec::new();
+    for entry in fs::read_dir(runs_dir)? {
+        let record_path = entry?.path().join("run.json");
+        if record_path.exists() {
+            runs.push(read_record(&record_path)?);
+        }
+    }
+    runs.sort_by(|a, b| {
+        (&a.timestamp, id_number(&a.id), &a.id).cmp(&(&b.timestamp, id_number(&b.id), &b.id))
+    });
+    Ok(runs)
+}
+
+/// Implements the load run function, which gives the saved run with the given id.
+/// `last` gives the most recent run, plans left out
+///
+/// # Errors
+/// Returns an error if the run does not exist
+/// Returns an error if the run record is not parsable
+pub fn load_run(state_dir: &Path, run_id: &str) -> Result<RunRecord, Box<dyn std::error::Error>> {
+    if run_id == "last" {
+        return list_runs(state_dir)?
+            .into_iter()
+            .rfind(|run| run.kind == RunKind::Run)
+            .ok_or_else(|| "There are no saved runs".into());
+    }
+    let record_path = state_dir.join("runs").join(run_id).join("run.json");
+    if !record_path.exists() {
+        return Err(format!("Run {} does not exist", run_id).into());
+    }
+    read_record(&record_path)
+}
+
+/// Implements the failed books function, which rebuilds the books of a saved run that did not
+/// succeed (failed, or skipped because a dependency failed), with their original extra vars
+/// and ansible options. Secrets were not saved (see `vars::redact`), so they are left out and
+/// have to be given again.
+/// The file nums are looked up again in `playbook_dir`, which should be the one the run
+/// recorded, so they stay correct if playbooks were added since the run
+///
+/// # Errors
+/// Returns an error if the run is a plan, whose playbooks were only checked
+/// Returns an error if the playbook directory cannot be read
+/// Returns an error if one of the playbooks no longer exists
+pub fn failed_books(
+    run: &RunRecord,
+    playbook_dir: &Path,
+) -> Result<BookEnvs, Box<dyn std::error::Error>> {
+    if run.kind == RunKind::Plan {
+        return Err(format!("Run {} is a plan, only runs can be rerun", run.id).into());
+    }
+    let mut map = Map::new();
+    for book in &run.books {
+        if book.status != Status::Success {
+            let num = args::map_num(&book.book, playbook_dir)?;
+            map.insert(
+                num,
+                (
+                    book.book.clone(),
+                    book.extra_vars
+                        .iter()
+                        .filter(|(name, _)| !vars::is_secret(name))
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect(),
+                    book.options.clone(),
+                ),
+            );
+        }
+    }
+    Ok(map)
+}
+
+/// Gives the number `new_run` put after an id to keep it unique, 0 when there is none
+fn id_number(id: &str) -> u64 {
+    id.rsplit_once('-')
+        .and_then(|(_, number)| number.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Reads a single `run.json` record
+fn read_record(path: &Path) -> Result<RunRecord, Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&contents)?)
+}
@@ -0,0 +1,351 @@
+use crate::error::Error;
+use crate::utilities::parse::{self, canonical, PlaySummary};
+use crate::utilities::playbook::{IncludeKind, PlaybookEntry, TaskEntry};
+use crate::utilities::roles;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+/// A single entry of a playbook, with the files it pulls in
+pub struct PlayNode {
+    pub summary: PlaySummary,
+    /// For a play, the tasks files and roles its tasks and handlers pull in. For an
+    /// `import_playbook`, the playbook it imports
+    pub includes: Vec<IncludeNode>,
+}
+
+/// A playbook, tasks file or role that is pulled in by another file
+pub struct IncludeNode {
+    pub kind: IncludeKind,
+    /// The file, or the name of the role, as it was written
+    pub target: String,
+    /// The tasks file of the role to run instead of `main`, for roles
+    pub tasks_from: Option<String>,
+    /// Where the file or role was found, or where it was looked for when it was not. None
+    /// when the target is templated
+    pub path: Option<PathBuf>,
+    /// Why the file could not be followed, if it could not
+    pub problem: Option<IncludeProblem>,
+    /// The plays of the playbook, for an `import_playbook`
+    pub plays: Vec<PlayNode>,
+    /// The tasks of the file, for a tasks file or role that could be followed
+    pub tasks: Vec<TaskEntry>,
+    /// The tasks files and roles the file pulls in in turn
+    pub includes: Vec<IncludeNode>,
+}
+
+/// The reasons a file that is pulled in cannot be followed
+pub enum IncludeProblem {
+    /// The file or role does not exist
+    Missing,
+    /// The file is already being pulled in further up, so following it would never end
+    Cycle,
+    /// The target is a jinja template, which is only known when the playbook runs
+    Templated,
+    /// The file is not a playbook or a list of tasks
+    Broken(String),
+}
+
+impl fmt::Display for IncludeProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IncludeProblem::Missing => write!(f, "does not exist"),
+            IncludeProblem::Cycle => write!(f, "includes itself"),
+            IncludeProblem::Templated => write!(f, "is templated, it is only known when run"),
+            IncludeProblem::Broken(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// Where files are looked for while walking a playbook
+#[derive(Clone)]
+struct Dirs {
+    /// The dir of the playbook the play is in, which roles and tasks files are found from
+    book: PathBuf,
+    /// The dir of the file that is being walked, which tasks files are found from first
+    file: PathBuf,
+}
+
+/// Implements the playbook tree function, which takes a given file and returns every entry of
+/// it with the files it pulls in, following `import_playbook`, `import_tasks`,
+/// `include_tasks`, `import_role` and `include_role` through every file they pull in
+///
+/// Playbooks are found from the dir of the playbook that imports them. Tasks files are found
+/// from the dir of the file that pulls them in, and then from the dir of the playbook. Roles
+/// are found in the roles path, see `roles::find_role`. A file
+/// that cannot be followed is kept in the tree with the reason why, see `IncludeProblem`
+///
+/// # Errors
+/// Returns an error if the file path is not valid
+/// Returns an error if the file is not parsable
+/// Returns an error if the file is not a list of plays
+pub fn playbook_tree(path: &PathBuf) -> Result<Vec<PlayNode>, Error> {
+    book_plays(path, &mut vec![canonical(path)])
+}
+
+/// Implements the include problems function, which gives every file of a playbook tree that
+/// could not be followed, with the chain of files that lead to it, e.g.
+/// `play 1: include_tasks setup.yaml > import_tasks users.yaml: does not exist`. Templated
+/// targets are left out, they are not wrong, just unknown until the playbook runs
+pub fn include_problems(plays: &[PlayNode]) -> Vec<String> {
+    let mut problems = Vec::new();
+    plays_problems(plays, "", &mut problems);
+    problems
+}
+
+/// Implements the flatten plays function, which gives every play of a playbook tree, with the
+/// plays of the playbooks it imports in place, in the order they run
+pub fn flatten_plays(plays: &[PlayNode]) -> Vec<PlaySummary> {
+    let mut flat = Vec::new();
+    for play in plays {
+        flat.push(play.summary.clone());
+        for include in &play.includes {
+            flat.extend(flatten_plays(&include.plays));
+        }
+    }
+    flat
+}
+
+/// Implements the role names function, which gives the name of every role a playbook tree
+/// runs, from the `roles` of its plays and its `import_role`s and `include_role`s, once, in
+/// the order they first appear
+pub fn role_names(plays: &[PlayNode]) -> Vec<String> {
+    let mut names = Vec::new();
+    for play in flatten_plays(plays) {
+        for role in play.roles {
+            if !names.contains(&role) {
+                names.push(role);
+            }
+        }
+    }
+    let mut included = Vec::new();
+    plays_roles(plays, &mut included);
+    for role in included {
+        if !names.contains(&role) {
+            names.push(role);
+        }
+    }
+    names
+}
+
+/// Walks the entries of a playbook, where `stack` holds the files that are being walked
+fn book_plays(path: &PathBuf, stack: &mut Vec<PathBuf>) -> Result<Vec<PlayNode>, Error> {
+    let playbook = parse::unwrap_playbook(path)?;
+    let book_dir = path.parent().unwrap_or(Path::new("")).to_path_buf();
+    let dirs = Dirs {
+        book: book_dir.clone(),
+        file: book_dir,
+    };
+    let mut plays = Vec::new();
+    for (i, entry) in playbook.entries.iter().enumerate() {
+        let includes = match entry {
+            PlaybookEntry::Play(play) => {
+                let mut includes = Vec::new();
+                for entries in [&play.pre_tasks, &play.tasks, &play.post_tasks] {
+                    includes.extend(task_includes(entries, &dirs, stack));
+                }
+                let handlers: Vec<TaskEntry> = play
+                    .handlers
+                    .iter()
+                    .map(|handler| TaskEntry::Task(handler.task.clone()))
+                    .collect();
+                includes.extend(task_includes(&handlers, &dirs, stack));
+                includes
+            }
+            PlaybookEntry::Import(import) => vec![follow(
+                IncludeKind::ImportPlaybook,
+                &import.import_playbook,
+                None,
+                &dirs,
+                stack,
+            )],
+        };
+        let mut tasks = Vec::new();
+        included_tasks(&includes, &mut tasks);
+        let mut tasks = tasks.into_iter();
+        let mut included = || tasks.next().unwrap_or_default();
+        plays.push(PlayNode {
+            summary: PlaySummary::new(i + 1, entry, &dirs.book, &mut included),
+            includes,
+        });
+    }
+    Ok(plays)
+}
+
+/// Walks a list of tasks, and the tasks inside its blocks
+fn task_includes(entries: &[TaskEntry], dirs: &Dirs, stack: &mut Vec<PathBuf>) -> Vec<IncludeNode> {
+    let mut includes = Vec::new();
+    for entry in entries {
+        match entry {
+            TaskEntry::Task(task) => {
+                if let Some(include) = task.include() {
+                    includes.push(follow(
+                        include.kind,
+                        &include.target,
+                        include.tasks_from.as_deref(),
+                        dirs,
+                        stack,
+                    ));
+                }
+            }
+            TaskEntry::Block(block) => {
+                for entries in [&block.block, &block.rescue, &block.always] {
+                    includes.extend(task_includes(entries, dirs, stack));
+                }
+            }
+        }
+    }
+    includes
+}
+
+/// Finds the file or role a single include pulls in and walks it
+fn follow(
+    kind: IncludeKind,
+    target: &str,
+    tasks_from: Option<&str>,
+    dirs: &Dirs,
+    stack: &mut Vec<PathBuf>,
+) -> IncludeNode {
+    let mut node = IncludeNode {
+        kind,
+        target: target.to_owned(),
+        tasks_from: tasks_from.map(str::to_owned),
+        path: None,
+        problem: None,
+        plays: Vec::new(),
+        tasks: Vec::new(),
+        includes: Vec::new(),
+    };
+    if target.contains("{{") || tasks_from.is_some_and(|file| file.contains("{{")) {
+        node.problem = Some(IncludeProblem::Templated);
+        return node;
+    }
+
+    let file = match kind {
+        IncludeKind::ImportPlaybook => {
+            let path = dirs.file.join(target);
+            node.path = Some(path.clone());
+            path.is_file().then_some(path)
+        }
+        IncludeKind::ImportTasks | IncludeKind::IncludeTasks => {
+            let candidates = [dirs.file.join(target), dirs.book.join(target)];
+            let found = candidates.iter().find(|path| path.is_file());
+            node.path = Some(found.unwrap_or(&candidates[0]).clone());
+            found.cloned()
+        }
+        IncludeKind::ImportRole | IncludeKind::IncludeRole => {
+            let found = roles::find_role(target, &dirs.book);
+            node.path = Some(
+                found
+                    .clone()
+                    .unwrap_or_else(|| dirs.book.join("roles").join(target)),
+            );
+            match found {
+                // A role does not need any tasks, unless they are asked for with tasks_from
+                Some(role) => match role_tasks(&role, tasks_from) {
+                    Some(tasks) => Some(tasks),
+                    None if tasks_from.is_some() => None,
+                    None => return node,
+                },
+                None => None,
+            }
+        }
+    };
+    let Some(file) = file else {
+        node.problem = Some(IncludeProblem::Missing);
+        return node;
+    };
+
+    let key = canonical(&file);
+    if stack.contains(&key) {
+        node.problem = Some(IncludeProblem::Cycle);
+        return node;
+    }
+    stack.push(key);
+    match kind {
+        IncludeKind::ImportPlaybook => match book_plays(&file, stack) {
+            Ok(plays) => node.plays = plays,
+            Err(err) => node.problem = Some(IncludeProblem::Broken(err.to_string())),
+        },
+        _ => match tasks_file(&file) {
+            Ok(entries) => {
+                let dirs = Dirs {
+                    book: dirs.book.clone(),
+                    file: file.parent().unwrap_or(Path::new("")).to_path_buf(),
+                };
+                node.includes = task_includes(&entries, &dirs, stack);
+                node.tasks = entries;
+            }
+            Err(err) => node.problem = Some(IncludeProblem::Broken(err.to_string())),
+        },
+    }
+    stack.pop();
+    node
+}
+
+/// Gives the tasks of every tasks file and role that is pulled in, in the order the tasks
+/// that pull them in are walked, see `playbook::Play::var_refs`
+fn included_tasks(includes: &[IncludeNode], tasks: &mut Vec<Vec<TaskEntry>>) {
+    for include in includes {
+        tasks.push(include.tasks.clone());
+        included_tasks(&include.includes, tasks);
+    }
+}
+
+/// Gives the tasks file of a role, `tasks/main.yml` unless another one is asked for
+fn role_tasks(role: &Path, tasks_from: Option<&str>) -> Option<PathBuf> {
+    let name = tasks_from.unwrap_or("main");
+    ["", ".yml", ".yaml"]
+        .iter()
+        .map(|extension| role.join("tasks").join(format!("{}{}", name, extension)))
+        .find(|path| path.is_file())
+}
+
+/// Reads a tasks file, where an empty file has no tasks
+fn tasks_file(path: &PathBuf) -> Result<Vec<TaskEntry>, Error> {
+    let contents = parse::contents(path)?;
+    serde_yaml::from_str::<Option<Vec<TaskEntry>>>(&contents)
+        .map(Option::unwrap_or_default)
+        .map_err(|err| Error::yaml(path, err))
+}
+
+fn plays_problems(plays: &[PlayNode], prefix: &str, problems: &mut Vec<String>) {
+    for play in plays {
+        let prefix = format!("{}play {}: ", prefix, play.summary.number);
+        includes_problems(&play.includes, &prefix, problems);
+    }
+}
+
+fn includes_problems(includes: &[IncludeNode], prefix: &str, problems: &mut Vec<String>) {
+    for include in includes {
+        let chain = format!("{}{} {}", prefix, include.kind, include.target);
+        if let Some(problem) = include
+            .problem
+            .as_ref()
+            .filter(|problem| !matches!(problem, IncludeProblem::Templated))
+        {
+            problems.push(format!("{}: {}", chain, problem));
+        }
+        plays_problems(&include.plays, &format!("{} > ", chain), problems);
+        includes_problems(&include.includes, &format!("{} > ", chain), problems);
+    }
+}
+
+fn plays_roles(plays: &[PlayNode], names: &mut Vec<String>) {
+    for play in plays {
+        includes_roles(&play.includes, names);
+    }
+}
+
+fn includes_roles(includes: &[IncludeNode], names: &mut Vec<String>) {
+    for include in includes {
+        let is_role = matches!(
+            include.kind,
+            IncludeKind::ImportRole | IncludeKind::IncludeRole
+        );
+        if is_role && !matches!(include.problem, Some(IncludeProblem::Templated)) {
+            names.push(include.target.clone());
+        }
+        plays_roles(&include.plays, names);
+        includes_roles(&include.includes, names);
+    }
+}
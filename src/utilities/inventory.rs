@@ -0,0 +1,168 @@
+use crate::utilities::{parse, playbook};
+use serde_yaml::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The dirs ansible loads variables from, next to the playbook and next to the inventory
+const VARS_DIRS: [&str; 2] = ["group_vars", "host_vars"];
+
+/// Implements the defined vars function, which gives the name of every variable the inventory
+/// sets for some host or group: the variables in `group_vars/` and `host_vars/` next to the
+/// playbook and next to the inventory, and the variables of the groups and hosts in the
+/// inventory itself, in yaml or ini. Files that cannot be read are left out, ansible says what
+/// is wrong with them when it runs
+pub fn defined_vars(book_dir: &Path, inventory: &Path) -> Vec<String> {
+    let inventory_dir = if inventory.is_dir() {
+        inventory
+    } else {
+        inventory.parent().unwrap_or(Path::new(""))
+    };
+    let mut names = Vec::new();
+    for dir in [book_dir, inventory_dir] {
+        for vars_dir in VARS_DIRS {
+            for file in vars_files(&dir.join(vars_dir)) {
+                if let Ok(Value::Mapping(vars)) = parse::unwrap(&file) {
+                    add_names(&mut names, vars.keys());
+                }
+            }
+        }
+    }
+    let sources = if inventory.is_dir() {
+        inventory_files(inventory)
+    } else {
+        vec![inventory.to_path_buf()]
+    };
+    for source in sources {
+        inventory_vars(&source, &mut names);
+    }
+    names
+}
+
+/// Gives every file under a `group_vars/` or `host_vars/` dir, where a group or host is either
+/// a file or a dir of files
+fn vars_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            files.extend(vars_files(&path));
+        } else if !is_hidden(&path) {
+            files.push(path);
+        }
+    }
+    files.sort();
+    files
+}
+
+/// Gives the files of an inventory dir, leaving out its dirs and hidden files
+fn inventory_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && !is_hidden(path))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Adds the variables of the groups and hosts of a single inventory file
+fn inventory_vars(path: &PathBuf, names: &mut Vec<String>) {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return;
+    };
+    match serde_yaml::from_str::<Value>(&contents) {
+        Ok(Value::Mapping(groups)) => groups.values().for_each(|group| group_vars(group, names)),
+        _ => ini_vars(&contents, names),
+    }
+}
+
+/// Adds the `vars` of a group of a yaml inventory, the variables of its `hosts` and those of
+/// its `children`
+fn group_vars(group: &Value, names: &mut Vec<String>) {
+    if let Some(Value::Mapping(vars)) = group.get("vars") {
+        add_names(names, vars.keys());
+    }
+    if let Some(Value::Mapping(hosts)) = group.get("hosts") {
+        for host in hosts.values() {
+            if let Value::Mapping(vars) = host {
+                add_names(names, vars.keys());
+            }
+        }
+    }
+    if let Some(Value::Mapping(children)) = group.get("children") {
+        children.values().for_each(|child| group_vars(child, names));
+    }
+}
+
+/// Adds the variables of an ini inventory: every `key=value` after a host, and every key in a
+/// `[group:vars]` section
+fn ini_vars(contents: &str, names: &mut Vec<String>) {
+    let mut in_vars = false;
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with(['#', ';']) {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            in_vars = section.ends_with(":vars");
+            continue;
+        }
+        let pairs: Vec<&str> = if in_vars {
+            vec![line]
+        } else {
+            line.split_whitespace().skip(1).collect()
+        };
+        for pair in pairs {
+            if let Some((key, _)) = pair.split_once('=') {
+                let key = key.trim().to_owned();
+                if !key.is_empty() && !names.contains(&key) {
+                    names.push(key);
+                }
+            }
+        }
+    }
+}
+
+fn add_names<'a>(names: &mut Vec<String>, keys: impl Iterator<Item = &'a Value>) {
+    for name in keys.filter_map(playbook::scalar_text) {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+}
+
+fn is_hidden(path: &Path) -> bool {
+    path.file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ini_vars_reads_host_and_group_vars() {
+        let mut names = Vec::new();
+        ini_vars(
+            "# comment\n[web]\nnode1 ansible_host=10.0.0.1 app_port=80\nnode2\n\n[web:vars]\nweb_user = www\n[all:children]\nweb\n",
+            &mut names,
+        );
+        assert_eq!(names, vec!["ansible_host", "app_port", "web_user"]);
+    }
+
+    #[test]
+    fn group_vars_reads_yaml_groups_hosts_and_children() {
+        let inventory: Value = serde_yaml::from_str(
+            "all:\n  vars: {region: eu}\n  hosts:\n    node1: {app_port: 80}\n    node2:\n  children:\n    web:\n      vars: {web_user: www}\n",
+        )
+        .unwrap();
+        let mut names = Vec::new();
+        group_vars(&inventory["all"], &mut names);
+        assert_eq!(names, vec!["region", "app_port", "web_user"]);
+    }
+}
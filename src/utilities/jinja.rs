@@ -0,0 +1,498 @@
+/// Words that are part of the jinja language, or that jinja fills in itself, and so are never
+/// variables
+const KEYWORDS: [&str; 24] = [
+    "and",
+    "or",
+    "not",
+    "in",
+    "is",
+    "if",
+    "else",
+    "elif",
+    "for",
+    "recursive",
+    "true",
+    "false",
+    "none",
+    "True",
+    "False",
+    "None",
+    "loop",
+    "self",
+    "super",
+    "caller",
+    "varargs",
+    "kwargs",
+    "with",
+    "without",
+];
+
+/// Variables ansible always sets itself, which are never given as extra vars. Every name that
+/// starts with `ansible_` (facts and connection vars) is left out as well
+const MAGIC_VARS: [&str; 16] = [
+    "item",
+    "hostvars",
+    "groups",
+    "group_names",
+    "inventory_hostname",
+    "inventory_hostname_short",
+    "inventory_dir",
+    "inventory_file",
+    "playbook_dir",
+    "play_hosts",
+    "role_name",
+    "role_names",
+    "role_path",
+    "omit",
+    "environment",
+    "vars",
+];
+
+/// Statements that do not refer to variables, see `template_refs`
+const STATEMENTS_WITHOUT_VARS: [&str; 6] =
+    ["filter", "import", "from", "extends", "block", "macro"];
+
+/// Tests that only check whether a variable is there, see `VarRef::optional`
+const DEFINED_TESTS: [&str; 2] = ["defined", "undefined"];
+
+/// Filters that give a value of their own when a variable is not there
+const DEFAULT_FILTERS: [&str; 2] = ["default", "d"];
+
+/// A variable a jinja expression or template refers to
+#[derive(Clone, Debug, PartialEq)]
+pub struct VarRef {
+    pub name: String,
+    /// Whether the expression still works when the variable is not given, because it has a
+    /// `default` filter (`{{ foo | default('x') }}`) or is guarded by an `is defined` test
+    /// (`foo is defined and foo > 1`)
+    pub optional: bool,
+}
+
+/// A single token of a jinja expression
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    /// A string or number literal
+    Literal,
+    Symbol(String),
+}
+
+/// Implements the template refs function, which gives every variable a piece of text refers
+/// to in its `{{ }}` expressions and `{% %}` statements, in the order they are first referred
+/// to. Comments and `{% raw %}` sections are skipped, and names bound by the template itself
+/// (`{% for x in xs %}`, `{% set x = ... %}`, macro names and arguments) are left out
+///
+/// e.g. `{{ pkg }}-{{ version | default('1') }}` refers to `pkg` and, optionally, `version`
+pub fn template_refs(text: &str) -> Vec<VarRef> {
+    // Every `{{ }}` and `{% %}` in order, with whether it is a statement
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    let mut raw = false;
+    while let Some(start) = rest.find('{') {
+        let open = &rest[start..];
+        let close = match open.get(..2) {
+            Some("{{") => "}}",
+            Some("{%") => "%}",
+            Some("{#") => "#}",
+            _ => {
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+        let Some(end) = open[2..].find(close) else {
+            break;
+        };
+        let inner = open[2..2 + end]
+            .trim_start_matches(['-', '+'])
+            .trim_end_matches(['-', '+'])
+            .trim();
+        rest = &open[2 + end + 2..];
+        match close {
+            "%}" if inner == "raw" => raw = true,
+            "%}" if inner == "endraw" => raw = false,
+            "#}" => {}
+            _ if raw => {}
+            "%}" => pieces.push((true, tokenize(inner))),
+            _ => pieces.push((false, tokenize(inner))),
+        }
+    }
+
+    let bound: Vec<String> = pieces
+        .iter()
+        .filter(|(statement, _)| *statement)
+        .flat_map(|(_, tokens)| bound(tokens))
+        .collect();
+    let mut refs = Vec::new();
+    for (statement, tokens) in &pieces {
+        // The first word of a statement says what it does, e.g. `if` or `endfor`. Statements
+        // that take template names or filters instead of expressions are left out
+        let tokens = match tokens.first() {
+            Some(Token::Name(word)) if *statement => {
+                if STATEMENTS_WITHOUT_VARS.contains(&word.as_str()) {
+                    continue;
+                }
+                &tokens[1..]
+            }
+            _ => &tokens[..],
+        };
+        for var in token_refs(tokens) {
+            if !bound.contains(&var.name) {
+                add_ref(&mut refs, var);
+            }
+        }
+    }
+    refs
+}
+
+/// Implements the expression refs function, which gives every variable a bare jinja
+/// expression refers to, such as a `when` condition, in the order they are first referred to.
+/// Text with `{{ }}` in it is read as a template instead, see `template_refs`
+///
+/// e.g. `foo is defined and foo > 1 and bar` refers to `foo`, optionally, and `bar`
+pub fn expression_refs(expression: &str) -> Vec<VarRef> {
+    if expression.contains("{{") || expression.contains("{%") {
+        return template_refs(expression);
+    }
+    let mut refs = Vec::new();
+    for var in token_refs(&tokenize(expression)) {
+        add_ref(&mut refs, var);
+    }
+    refs
+}
+
+/// Implements the guards function, which gives every variable a bare jinja expression
+/// checks with an `is defined` (or `is undefined`) test. A task whose `when` checks a
+/// variable like this does not need it to be given
+pub fn guards(expression: &str) -> Vec<String> {
+    let tokens = tokenize(expression);
+    let mut names = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        if let Token::Name(name) = token {
+            if is_variable(&tokens, i)
+                && is_guarded(&tokens, chain_end(&tokens, i))
+                && !names.contains(name)
+            {
+                names.push(name.clone());
+            }
+        }
+    }
+    names
+}
+
+/// Adds a variable to a list of them, where a variable that is already there is only
+/// optional if it is optional both times
+pub fn add_ref(refs: &mut Vec<VarRef>, var: VarRef) {
+    match refs.iter_mut().find(|other| other.name == var.name) {
+        Some(other) => other.optional &= var.optional,
+        None => refs.push(var),
+    }
+}
+
+/// Gives every variable the tokens of a single expression refer to. A variable the
+/// expression checks with `is defined` is optional everywhere in that expression, e.g.
+/// both `foo`s in `foo if foo is defined else 'x'`
+fn token_refs(tokens: &[Token]) -> Vec<VarRef> {
+    let mut refs: Vec<VarRef> = Vec::new();
+    let mut guarded: Vec<&str> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let Token::Name(name) = token else {
+            continue;
+        };
+        if !is_variable(tokens, i) {
+            continue;
+        }
+        let end = chain_end(tokens, i);
+        let is_guard = is_guarded(tokens, end);
+        if is_guard {
+            guarded.push(name);
+        }
+        add_ref(
+            &mut refs,
+            VarRef {
+                name: name.clone(),
+                optional: is_guard || is_defaulted(tokens, end),
+            },
+        );
+    }
+    for var in &mut refs {
+        var.optional |= guarded.contains(&var.name.as_str());
+    }
+    refs
+}
+
+/// Gives whether the name at `i` is a variable, and not an attribute, a filter, a test, a
+/// function, a keyword argument or a name jinja or ansible fill in themselves
+fn is_variable(tokens: &[Token], i: usize) -> bool {
+    let Some(Token::Name(name)) = tokens.get(i) else {
+        return false;
+    };
+    let previous = i.checked_sub(1).and_then(|j| tokens.get(j));
+    let before_previous = i.checked_sub(2).and_then(|j| tokens.get(j));
+    let after_test = match (before_previous, previous) {
+        (_, Some(Token::Name(word))) if word == "is" => true,
+        (Some(Token::Name(is)), Some(Token::Name(not))) => is == "is" && not == "not",
+        _ => false,
+    };
+    let called =
+        matches!(tokens.get(i + 1), Some(Token::Symbol(symbol)) if symbol == "(" || symbol == "=");
+    !(matches!(previous, Some(Token::Symbol(symbol)) if symbol == "." || symbol == "|")
+        || after_test
+        || called
+        || KEYWORDS.contains(&name.as_str())
+        || MAGIC_VARS.contains(&name.as_str())
+        || name.starts_with("ansible_"))
+}
+
+/// Gives where the attribute and index lookups after the name at `i` end, e.g. after
+/// `foo.bar['baz']`
+fn chain_end(tokens: &[Token], i: usize) -> usize {
+    let mut end = i + 1;
+    loop {
+        match tokens.get(end) {
+            Some(Token::Symbol(symbol)) if symbol == "." => end += 2,
+            Some(Token::Symbol(symbol)) if symbol == "[" => {
+                let mut depth = 0;
+                while let Some(token) = tokens.get(end) {
+                    match token {
+                        Token::Symbol(symbol) if symbol == "[" => depth += 1,
+                        Token::Symbol(symbol) if symbol == "]" => depth -= 1,
+                        _ => {}
+                    }
+                    end += 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+            }
+            _ => return end,
+        }
+    }
+}
+
+/// Gives whether the tokens at `end` are a `default` filter
+fn is_defaulted(tokens: &[Token], end: usize) -> bool {
+    matches!(
+        (tokens.get(end), tokens.get(end + 1)),
+        (Some(Token::Symbol(pipe)), Some(Token::Name(filter)))
+            if pipe == "|" && DEFAULT_FILTERS.contains(&filter.as_str())
+    )
+}
+
+/// Gives whether the tokens at `end` are an `is defined` test, or one of its variants
+fn is_guarded(tokens: &[Token], end: usize) -> bool {
+    let is_test = |token: Option<&Token>| matches!(token, Some(Token::Name(test)) if DEFINED_TESTS.contains(&test.as_str()));
+    match (tokens.get(end), tokens.get(end + 1)) {
+        (Some(Token::Name(is)), Some(Token::Name(not))) if is == "is" && not == "not" => {
+            is_test(tokens.get(end + 2))
+        }
+        (Some(Token::Name(is)), next) if is == "is" => is_test(next),
+        _ => false,
+    }
+}
+
+/// Gives the names a single statement binds, e.g. `x` in `for x in xs`
+fn bound(tokens: &[Token]) -> Vec<String> {
+    let names = |tokens: &[Token]| -> Vec<String> {
+        tokens
+            .iter()
+            .filter_map(|token| match token {
+                Token::Name(name) => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    match tokens.first() {
+        Some(Token::Name(word)) if word == "for" => {
+            let end = tokens
+                .iter()
+                .position(|token| *token == Token::Name("in".to_owned()))
+                .unwrap_or(tokens.len());
+            names(&tokens[1..end])
+        }
+        Some(Token::Name(word)) if word == "set" => {
+            let end = tokens
+                .iter()
+                .position(|token| *token == Token::Symbol("=".to_owned()))
+                .unwrap_or(tokens.len());
+            names(&tokens[1..end])
+        }
+        Some(Token::Name(word)) if word == "macro" || word == "call" => {
+            let end = tokens
+                .iter()
+                .position(|token| *token == Token::Symbol(")".to_owned()))
+                .unwrap_or(tokens.len());
+            names(&tokens[1..end])
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Splits a jinja expression into names, literals and symbols
+fn tokenize(expression: &str) -> Vec<Token> {
+    let chars: Vec<char> = expression.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else if c.is_ascii_digit() {
+            while i < chars.len()
+                && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+            {
+                i += 1;
+            }
+            tokens.push(Token::Literal);
+        } else if c == '\'' || c == '"' {
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                i += if chars[i] == '\\' { 2 } else { 1 };
+            }
+            i += 1;
+            tokens.push(Token::Literal);
+        } else {
+            let pair: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            if ["==", "!=", "<=", ">=", "//", "**"].contains(&pair.as_str()) {
+                tokens.push(Token::Symbol(pair));
+                i += 2;
+            } else {
+                tokens.push(Token::Symbol(c.to_string()));
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn var(name: &str, optional: bool) -> VarRef {
+        VarRef {
+            name: name.to_owned(),
+            optional,
+        }
+    }
+
+    #[test]
+    fn template_refs_finds_expressions_and_statements() {
+        assert_eq!(
+            template_refs("{{ pkg }}-{{ version }} {% if enabled %}on{% endif %}"),
+            vec![
+                var("pkg", false),
+                var("version", false),
+                var("enabled", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn default_filter_makes_a_var_optional() {
+        assert_eq!(
+            template_refs("{{ version | default('1') }} {{ port|d(80) }}"),
+            vec![var("version", true), var("port", true)]
+        );
+    }
+
+    #[test]
+    fn a_var_is_only_optional_if_it_is_everywhere() {
+        assert_eq!(
+            template_refs("{{ version | default('1') }} {{ version }}"),
+            vec![var("version", false)]
+        );
+    }
+
+    #[test]
+    fn defined_tests_make_a_var_optional() {
+        assert_eq!(
+            expression_refs("foo is defined and foo > 1 and bar"),
+            vec![var("foo", true), var("bar", false)]
+        );
+        assert_eq!(
+            expression_refs("foo is not defined or foo == 'x'"),
+            vec![var("foo", true)]
+        );
+        assert_eq!(expression_refs("foo is undefined"), vec![var("foo", true)]);
+    }
+
+    #[test]
+    fn bound_names_are_left_out() {
+        assert_eq!(
+            template_refs("{% for user in users %}{{ user.name }}{{ suffix }}{% endfor %}"),
+            vec![var("users", false), var("suffix", false)]
+        );
+        assert_eq!(
+            template_refs("{% set greeting = prefix ~ 'hi' %}{{ greeting }}"),
+            vec![var("prefix", false)]
+        );
+    }
+
+    #[test]
+    fn raw_sections_and_comments_are_skipped() {
+        assert_eq!(
+            template_refs("{% raw %}{{ not_a_var }}{% endraw %}{# {{ nor_this }} #}{{ real }}"),
+            vec![var("real", false)]
+        );
+    }
+
+    #[test]
+    fn attribute_and_index_chains_refer_to_the_first_name() {
+        assert_eq!(
+            template_refs("{{ user.name }} {{ config['db'].host }} {{ items[idx] }}"),
+            vec![
+                var("user", false),
+                var("config", false),
+                var("items", false),
+                var("idx", false)
+            ]
+        );
+        assert_eq!(
+            expression_refs("config['db'].host is defined"),
+            vec![var("config", true)]
+        );
+    }
+
+    #[test]
+    fn filters_functions_and_keyword_args_are_left_out() {
+        assert_eq!(
+            template_refs("{{ names | join(sep) }} {{ lookup('env', 'HOME') }}"),
+            vec![var("names", false), var("sep", false)]
+        );
+        assert_eq!(
+            template_refs("{{ data | to_json(indent=width, sort_keys=true) }}"),
+            vec![var("data", false), var("width", false)]
+        );
+    }
+
+    #[test]
+    fn magic_vars_and_keywords_are_left_out() {
+        assert_eq!(
+            template_refs("{{ inventory_hostname }} {{ ansible_facts.os }} {{ item }} {{ none }}"),
+            Vec::new()
+        );
+    }
+
+    #[test]
+    fn expression_refs_reads_templates_as_templates() {
+        assert_eq!(
+            expression_refs("{{ greeting }} world"),
+            vec![var("greeting", false)]
+        );
+    }
+
+    #[test]
+    fn guards_gives_the_vars_checked_with_defined() {
+        assert_eq!(
+            guards("foo is defined and bar.baz is not defined and qux > 1"),
+            vec!["foo".to_owned(), "bar".to_owned()]
+        );
+        assert!(guards("foo | default(false)").is_empty());
+    }
+}
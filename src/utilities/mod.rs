@@ -1,8 +1,41 @@
 pub mod parse;
 pub use parse::contents;
+pub use parse::merge_envs;
+pub use parse::structure_errors;
 pub use parse::unwrap;
+pub use parse::unwrap_deps;
 pub use parse::unwrap_envs;
+pub use parse::unwrap_header;
 pub use parse::unwrap_name;
+pub use parse::unwrap_playbook;
+pub use parse::unwrap_plays;
+pub use parse::unwrap_timeout;
+pub use parse::PlaySummary;
+
+pub mod jinja;
+pub use jinja::expression_refs;
+pub use jinja::template_refs;
+pub use jinja::VarRef;
+
+pub mod playbook;
+pub use playbook::Block;
+pub use playbook::Handler;
+pub use playbook::ImportPlaybook;
+pub use playbook::IncludeKind;
+pub use playbook::Play;
+pub use playbook::Playbook;
+pub use playbook::RoleRef;
+pub use playbook::Task;
+pub use playbook::TaskEntry;
+pub use playbook::TaskInclude;
+
+pub mod includes;
+pub use includes::flatten_plays;
+pub use includes::include_problems;
+pub use includes::playbook_tree;
+pub use includes::role_names;
+pub use includes::IncludeNode;
+pub use includes::PlayNode;
 
 pub mod args;
 pub use args::arg_parse;
@@ -10,3 +43,65 @@ pub use args::arg_parse_env;
 pub use args::map_files;
 pub use args::map_name;
 pub use args::map_num;
+pub use args::BookEnvs;
+
+pub mod inventory;
+pub use inventory::defined_vars;
+
+pub mod deps;
+pub use deps::dependency_graph;
+
+pub mod report;
+pub use report::write_report;
+pub use report::ReportFormat;
+
+pub mod history;
+pub use history::failed_books;
+pub use history::list_runs;
+pub use history::load_run;
+pub use history::new_run;
+pub use history::save_run;
+pub use history::RunKind;
+
+pub mod stream;
+pub use stream::stream;
+pub use stream::StreamOptions;
+
+pub mod recap;
+pub use recap::parse_recap;
+pub use recap::recap_table;
+pub use recap::HostStats;
+
+pub mod diff;
+pub use diff::line_diff;
+pub use diff::render_diff;
+
+pub mod callback;
+pub use callback::parse_output;
+pub use callback::task_failures;
+pub use callback::TaskResult;
+
+pub mod vars;
+pub use vars::book_vars;
+pub use vars::load_vars_file;
+pub use vars::parse_vars;
+pub use vars::ExtraVars;
+
+pub mod options;
+pub use options::AnsibleOptions;
+
+pub mod roles;
+pub use roles::find_role;
+pub use roles::role_info;
+pub use roles::RoleInfo;
+pub use roles::RoleVar;
+
+pub mod validate;
+pub use validate::missing_vars;
+pub use validate::role_var_mismatches;
+pub use validate::MissingVar;
+pub use validate::RoleVarMismatch;
+
+pub mod prompt;
+pub use prompt::prompt_missing;
+pub use prompt::save_answers;
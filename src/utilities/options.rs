@@ -0,0 +1,288 @@
+use crate::utilities::vars;
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The `ansible-playbook` options that pick what a playbook runs against and how, given for
+/// the whole batch on the command line or for a single playbook in the `book.yaml,...` syntax
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize, Args)]
+pub struct AnsibleOptions {
+    /// Only run against these hosts or groups
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<String>,
+    /// Only run tasks with these tags
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Skip tasks with these tags
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skip_tags: Vec<String>,
+    /// Do not change anything, only report what would change
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub check: bool,
+    /// Show the differences in changed files and templates
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub diff: bool,
+    /// How many hosts ansible works on at the same time
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forks: Option<u32>,
+    /// Run the tasks with privilege escalation
+    #[arg(long = "become")]
+    #[serde(rename = "become", default, skip_serializing_if = "is_false")]
+    pub become_root: bool,
+    /// Start the playbook at the task with this name
+    #[arg(long)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_at_task: Option<String>,
+}
+
+impl AnsibleOptions {
+    /// Implements the parse option function, which sets a single option given for a playbook
+    /// in the `book.yaml,...` syntax, e.g. `--limit=web`, `--check`, `--forks=5` or
+    /// `--tags=[setup, deploy]`. Values are read like extra vars, see `vars::parse_vars`, so
+    /// `--tags='setup,deploy'` also works
+    ///
+    /// # Errors
+    /// Returns an error if the option is not known
+    /// Returns an error if the value is missing or of the wrong type
+    pub fn parse_option(&mut self, option: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let option = option.trim();
+        let (name, value) = match option.split_once('=') {
+            Some(_) => vars::parse_pair(option)?,
+            None => (option.to_owned(), Value::Bool(true)),
+        };
+        match name.as_str() {
+            "--limit" => self.limit = Some(option_string(&name, &value)?),
+            "--tags" => self.tags = option_list(&name, &value)?,
+            "--skip-tags" => self.skip_tags = option_list(&name, &value)?,
+            "--check" => self.check = option_bool(&name, &value)?,
+            "--diff" => self.diff = option_bool(&name, &value)?,
+            "--forks" => {
+                let forks = value
+                    .as_u64()
+                    .and_then(|forks| u32::try_from(forks).ok())
+                    .ok_or_else(|| format!("Option {} must be a number", name))?;
+                self.forks = Some(forks);
+            }
+            "--become" => self.become_root = option_bool(&name, &value)?,
+            "--start-at-task" => self.start_at_task = Some(option_string(&name, &value)?),
+            _ => return Err(format!("Unknown ansible option: {}", name).into()),
+        }
+        Ok(())
+    }
+
+    /// Implements the merged function, which gives the options of a single playbook given
+    /// `self` as the options of the whole batch. The options set for the playbook win, and
+    /// flags set for the batch stay set
+    pub fn merged(&self, book: &AnsibleOptions) -> AnsibleOptions {
+        AnsibleOptions {
+            limit: book.limit.clone().or_else(|| self.limit.clone()),
+            tags: if book.tags.is_empty() {
+                self.tags.clone()
+            } else {
+                book.tags.clone()
+            },
+            skip_tags: if book.skip_tags.is_empty() {
+                self.skip_tags.clone()
+            } else {
+                book.skip_tags.clone()
+            },
+            check: self.check || book.check,
+            diff: self.diff || book.diff,
+            forks: book.forks.or(self.forks),
+            become_root: self.become_root || book.become_root,
+            start_at_task: book
+                .start_at_task
+                .clone()
+                .or_else(|| self.start_at_task.clone()),
+        }
+    }
+
+    /// Implements the to args function, which gives the options as `ansible-playbook`
+    /// arguments
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(limit) = &self.limit {
+            args.push("--limit".to_owned());
+            args.push(limit.clone());
+        }
+        if !self.tags.is_empty() {
+            args.push("--tags".to_owned());
+            args.push(self.tags.join(","));
+        }
+        if !self.skip_tags.is_empty() {
+            args.push("--skip-tags".to_owned());
+            args.push(self.skip_tags.join(","));
+        }
+        if self.check {
+            args.push("--check".to_owned());
+        }
+        if self.diff {
+            args.push("--diff".to_owned());
+        }
+        if let Some(forks) = self.forks {
+            args.push("--forks".to_owned());
+            args.push(forks.to_string());
+        }
+        if self.become_root {
+            args.push("--become".to_owned());
+        }
+        if let Some(task) = &self.start_at_task {
+            args.push("--start-at-task".to_owned());
+            args.push(task.clone());
+        }
+        args
+    }
+
+    /// Gives whether no option is set
+    pub fn is_empty(&self) -> bool {
+        self == &AnsibleOptions::default()
+    }
+}
+
+impl std::fmt::Display for AnsibleOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let args: Vec<String> = self
+            .to_args()
+            .into_iter()
+            .map(|arg| {
+                if arg.is_empty() || arg.contains(char::is_whitespace) {
+                    format!("{:?}", arg)
+                } else {
+                    arg
+                }
+            })
+            .collect();
+        write!(f, "{}", args.join(" "))
+    }
+}
+
+/// Reads the value of an option that takes text
+fn option_string(name: &str, value: &Value) -> Result<String, Box<dyn std::error::Error>> {
+    match value {
+        Value::Bool(_) => Err(format!("Option {} needs a value, e.g. {}=VALUE", name, name).into()),
+        Value::Array(_) | Value::Object(_) => {
+            Err(format!("Option {} must be a single value", name).into())
+        }
+        value => Ok(vars::format_value(value)),
+    }
+}
+
+/// Reads the value of an option that takes a list, either a yaml flow list or comma
+/// separated text
+fn option_list(name: &str, value: &Value) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    match value {
+        Value::Array(items) => Ok(items.iter().map(vars::format_value).collect()),
+        value => Ok(option_string(name, value)?
+            .split(',')
+            .map(|item| item.trim().to_owned())
+            .filter(|item| !item.is_empty())
+            .collect()),
+    }
+}
+
+/// Reads the value of a flag, which is set when it is given without a value
+fn option_bool(name: &str, value: &Value) -> Result<bool, Box<dyn std::error::Error>> {
+    value
+        .as_bool()
+        .ok_or_else(|| format!("Option {} must be true or false", name).into())
+}
+
+/// Lets serde leave out flags that are not set
+fn is_false(flag: &bool) -> bool {
+    !flag
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(options: &[&str]) -> AnsibleOptions {
+        let mut parsed = AnsibleOptions::default();
+        for option in options {
+            parsed.parse_option(option).unwrap();
+        }
+        parsed
+    }
+
+    #[test]
+    fn parse_option_reads_every_option() {
+        let options = parsed(&[
+            "--limit=web",
+            "--tags=[setup, deploy]",
+            "--skip-tags='slow,flaky'",
+            "--check",
+            "--diff=false",
+            "--forks=5",
+            "--become",
+            "--start-at-task='Install packages'",
+        ]);
+        assert_eq!(options.limit.as_deref(), Some("web"));
+        assert_eq!(options.tags, vec!["setup", "deploy"]);
+        assert_eq!(options.skip_tags, vec!["slow", "flaky"]);
+        assert!(options.check);
+        assert!(!options.diff);
+        assert_eq!(options.forks, Some(5));
+        assert!(options.become_root);
+        assert_eq!(options.start_at_task.as_deref(), Some("Install packages"));
+    }
+
+    #[test]
+    fn parse_option_rejects_unknown_options_and_bad_values() {
+        let mut options = AnsibleOptions::default();
+        assert_eq!(
+            options.parse_option("--verbose").unwrap_err().to_string(),
+            "Unknown ansible option: --verbose"
+        );
+        assert_eq!(
+            options.parse_option("--limit").unwrap_err().to_string(),
+            "Option --limit needs a value, e.g. --limit=VALUE"
+        );
+        assert_eq!(
+            options
+                .parse_option("--forks=many")
+                .unwrap_err()
+                .to_string(),
+            "Option --forks must be a number"
+        );
+        assert_eq!(
+            options
+                .parse_option("--check=maybe")
+                .unwrap_err()
+                .to_string(),
+            "Option --check must be true or false"
+        );
+    }
+
+    #[test]
+    fn merged_lets_book_options_override_global_ones() {
+        let global = parsed(&["--limit=all", "--tags=setup", "--forks=10", "--check"]);
+        let book = parsed(&["--limit=web", "--skip-tags=slow", "--forks=2"]);
+        let merged = global.merged(&book);
+        assert_eq!(merged.limit.as_deref(), Some("web"));
+        assert_eq!(merged.tags, vec!["setup"]);
+        assert_eq!(merged.skip_tags, vec!["slow"]);
+        assert_eq!(merged.forks, Some(2));
+        assert!(merged.check);
+    }
+
+    #[test]
+    fn merged_keeps_global_options_the_book_does_not_set() {
+        let global = parsed(&["--limit=all", "--become"]);
+        assert!(global.merged(&AnsibleOptions::default()) == global);
+    }
+
+    #[test]
+    fn to_args_gives_ansible_playbook_arguments() {
+        let options = parsed(&["--limit=web", "--tags=[a, b]", "--check", "--forks=3"]);
+        assert_eq!(
+            options.to_args(),
+            vec!["--limit", "web", "--tags", "a,b", "--check", "--forks", "3"]
+        );
+    }
+}
@@ -1,15 +1,16 @@
+use crate::error::Error;
+use crate::utilities::jinja::VarRef;
+use crate::utilities::playbook::{self, Playbook, PlaybookEntry, TaskEntry};
 use serde_yaml;
-use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 /// Implements the contents function which just gives the entire data of the file
 ///
 /// # Errors
 /// Returns an error if the path is not valid
-pub fn contents(path: &PathBuf) -> Result<String, Box<dyn std::error::Error>> {
-    let contents = std::fs::read_to_string(path).unwrap_or_else(|_| file_not_found(path));
-    Ok(contents)
+pub fn contents(path: &PathBuf) -> Result<String, Error> {
+    std::fs::read_to_string(path).map_err(|_| Error::PlaybookNotFound(path.clone()))
 }
 
 /// Implements the unwrap function which takes a given file and returns a yaml object
@@ -17,81 +18,331 @@ pub fn contents(path: &PathBuf) -> Result<String, Box<dyn std::error::Error>> {
 /// # Errors
 /// Returns an error if the file path is not valid
 /// Returns an error if the file is not parsable
-pub fn unwrap(path: &PathBuf) -> Result<serde_yaml::Value, Box<dyn std::error::Error>> {
-    let contents = std::fs::read_to_string(path).unwrap_or_else(|_| file_not_found(path));
-    let deserialized = serde_yaml::from_str::<serde_yaml::Value>(&contents)
-        .unwrap_or_else(|_| file_not_parsable(path));
-    Ok(deserialized)
+pub fn unwrap(path: &PathBuf) -> Result<serde_yaml::Value, Error> {
+    let contents = contents(path)?;
+    serde_yaml::from_str::<serde_yaml::Value>(&contents).map_err(|err| Error::yaml(path, err))
+}
+
+/// Implements the unwrap_playbook function which takes a given file and returns it as a
+/// typed playbook, see `playbook::Playbook`
+///
+/// # Errors
+/// Returns an error if the file path is not valid
+/// Returns an error if the file is not parsable, or does not fit the playbook model
+/// Returns an error if the file is not a list of plays
+pub fn unwrap_playbook(path: &PathBuf) -> Result<Playbook, Error> {
+    let contents = contents(path)?;
+    let plays = match serde_yaml::from_str::<serde_yaml::Value>(&contents) {
+        Ok(serde_yaml::Value::Sequence(plays)) if !plays.is_empty() => plays,
+        Ok(_) => return Err(Error::NotAPlaybook(path.clone())),
+        Err(err) => return Err(Error::yaml(path, err)),
+    };
+    serde_yaml::from_str::<Playbook>(&contents).map_err(|err| {
+        // The model reads every play as a whole, so its own error does not say which
+        // keyword was wrong
+        let detail = plays.iter().enumerate().find_map(|(i, play)| {
+            playbook::entry_errors(play)
+                .first()
+                .map(|error| format!("play {}: {}", i + 1, error))
+        });
+        match detail {
+            Some(message) => Error::Yaml {
+                path: path.clone(),
+                message,
+            },
+            None => Error::yaml(path, err),
+        }
+    })
 }
 
 /// Implements the unwrap_name function which takes a given file and returns a string
-/// with the value of the name field
+/// with the value of the name field of its first play. When the first entry is an
+/// `import_playbook` without a name, the name of the playbook it imports is given. When
+/// there is no name to be found, because the play has none or the imported playbook cannot
+/// be followed, the file name is given instead
 ///
 /// # Errors
 /// Returns an error if the file path is not valid
 /// Returns an error if the file is not parsable
-/// Returns an error if the name field is not there
-pub fn unwrap_name(path: &PathBuf) -> Result<String, Box<dyn std::error::Error>> {
-    let mut file = File::open(path).unwrap_or_else(|_| file_not_found(path));
-    let mut yaml_str = String::new();
-    file.read_to_string(&mut yaml_str)
-        .unwrap_or_else(|_| file_not_parsable(path));
-    let value_seq: serde_yaml::Sequence =
-        serde_yaml::from_str(&yaml_str).unwrap_or_else(|_| file_not_parsable(path));
-    let mut value = value_seq.get(0).unwrap();
-    value = &value["name"];
-    let output = value.as_str().unwrap();
-    Ok(output.to_string())
+pub fn unwrap_name(path: &Path) -> Result<String, Error> {
+    let file_name = || {
+        let name = path.file_name().unwrap_or(path.as_os_str());
+        name.to_string_lossy().into_owned()
+    };
+    let mut playbook = unwrap_playbook(&path.to_path_buf())?;
+    let mut current = path.to_path_buf();
+    let mut seen = vec![canonical(path)];
+    loop {
+        if let Some(name) = playbook.entries.first().and_then(PlaybookEntry::name) {
+            return Ok(name.to_string());
+        }
+        let Some(PlaybookEntry::Import(import)) = playbook.entries.first() else {
+            return Ok(file_name());
+        };
+        if import.import_playbook.contains("{{") {
+            return Ok(file_name());
+        }
+        current = current
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(&import.import_playbook);
+        let key = canonical(&current);
+        if seen.contains(&key) {
+            return Ok(file_name());
+        }
+        seen.push(key);
+        playbook = match unwrap_playbook(&current) {
+            Ok(playbook) => playbook,
+            Err(_) => return Ok(file_name()),
+        };
+    }
+}
+
+/// Gives the path a file is known by, which is the same however it was written, e.g.
+/// `./site.yaml` and `site.yaml`
+pub fn canonical(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
 }
-/// Implements the unwrap_envs function which takes a given file and returns all the
-/// ansible variables in it
+
+/// Implements the unwrap_envs function which takes a given file and returns the name of every
+/// ansible variable it refers to, from every play, required or optional. A variable several
+/// plays refer to is only given once, see `merge_envs`
 ///
 /// # Errors
 /// Returns an error if the file path is not valid
 /// Returns an error if the file is not parsable
-pub fn unwrap_envs(path: &PathBuf) -> Result<Vec<String>, Box<dyn std::error::Error>> {
-    let mut file = File::open(path).unwrap_or_else(|_| file_not_found(path));
-    let mut yaml_str = String::new();
-    file.read_to_string(&mut yaml_str)
-        .unwrap_or_else(|_| file_not_parsable(path));
-    let value_seq: serde_yaml::Sequence =
-        serde_yaml::from_str(&yaml_str).unwrap_or_else(|_| file_not_parsable(path));
-    let mut value = value_seq.get(0).unwrap();
-    if !value.get("vars").is_some() {
-        return Ok(Vec::new());
+pub fn unwrap_envs(path: &PathBuf) -> Result<Vec<String>, Error> {
+    let plays = unwrap_plays(path)?;
+    Ok(merge_envs(&plays)
+        .into_iter()
+        .map(|(env, _)| env.name)
+        .collect())
+}
+
+/// A single play of a playbook
+#[derive(Clone)]
+pub struct PlaySummary {
+    /// Where the play is in the playbook, starting at 1
+    pub number: usize,
+    pub name: Option<String>,
+    pub hosts: Vec<String>,
+    /// The names of the variables the play sets in its `vars`
+    pub vars: Vec<String>,
+    pub roles: Vec<String>,
+    /// How many tasks the play has in `pre_tasks`, `tasks` and `post_tasks`, counting the
+    /// tasks inside blocks
+    pub task_count: usize,
+    /// The ansible variables the play refers to and does not set itself, see
+    /// `playbook::Play::var_refs`
+    pub envs: Vec<VarRef>,
+    /// The playbook file the entry imports, if it is an `import_playbook`
+    pub import: Option<String>,
+}
+
+impl PlaySummary {
+    /// Gives the summary of a single entry of a playbook, where `book_dir` is the dir the
+    /// playbook is in, which the files the entry reads are found from, and `included` gives
+    /// the tasks its includes pull in, see `playbook::Play::var_refs`
+    pub fn new(
+        number: usize,
+        entry: &PlaybookEntry,
+        book_dir: &Path,
+        included: &mut dyn FnMut() -> Vec<TaskEntry>,
+    ) -> PlaySummary {
+        let read = |file: &str| std::fs::read_to_string(book_dir.join(file)).ok();
+        let envs = entry.var_refs(&read, included);
+        match entry {
+            PlaybookEntry::Play(play) => PlaySummary {
+                number,
+                name: play.name.clone(),
+                hosts: play.hosts.clone(),
+                vars: play.vars.keys().filter_map(playbook::scalar_text).collect(),
+                roles: play
+                    .roles
+                    .iter()
+                    .map(|role| role.name().to_owned())
+                    .collect(),
+                task_count: play.all_tasks().len(),
+                envs,
+                import: None,
+            },
+            PlaybookEntry::Import(import) => PlaySummary {
+                number,
+                name: import.name.clone(),
+                hosts: Vec::new(),
+                vars: import
+                    .vars
+                    .keys()
+                    .filter_map(playbook::scalar_text)
+                    .collect(),
+                roles: Vec::new(),
+                task_count: 0,
+                envs,
+                import: Some(import.import_playbook.clone()),
+            },
+        }
     }
-    value = &value.get("vars").unwrap();
-    let output = value.as_mapping().unwrap().values();
-    let mut result = Vec::new();
-    for i in output {
-        let istr_res = i.as_str();
-        match istr_res {
-            Some(istr) => {
-                if istr.contains("{{") && istr.contains("}}") {
-                    let start = istr.find("{{").unwrap() + 2;
-                    let end = istr.find("}}").unwrap();
-                    let mut var = i.as_str().unwrap().get(start..end).unwrap();
-                    var = var.trim();
-                    result.push(var.to_string());
+
+    /// Gives the name of the play, or `play <number>` if it has none
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("play {}", self.number),
+        }
+    }
+}
+
+/// Implements the unwrap_plays function which takes a given file and returns a summary of
+/// every play in it, in order. The files the plays pull in are not followed, see
+/// `includes::playbook_tree` for that
+///
+/// # Errors
+/// Returns an error if the file path is not valid
+/// Returns an error if the file is not parsable
+/// Returns an error if the file is not a list of plays
+pub fn unwrap_plays(path: &PathBuf) -> Result<Vec<PlaySummary>, Error> {
+    let playbook = unwrap_playbook(path)?;
+    let book_dir = path.parent().unwrap_or(Path::new(""));
+    Ok(playbook
+        .entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| PlaySummary::new(i + 1, entry, book_dir, &mut Vec::new))
+        .collect())
+}
+
+/// Implements the merge_envs function which gives every ansible variable the plays refer to
+/// once, with the labels of the plays that refer to it, in the order they are first referred
+/// to. A variable is only optional if it is optional in every play that refers to it
+pub fn merge_envs(plays: &[PlaySummary]) -> Vec<(VarRef, Vec<String>)> {
+    let mut merged: Vec<(VarRef, Vec<String>)> = Vec::new();
+    for play in plays {
+        for env in &play.envs {
+            let label = play.label();
+            match merged.iter_mut().find(|(other, _)| other.name == env.name) {
+                Some((other, labels)) => {
+                    other.optional &= env.optional;
+                    if !labels.contains(&label) {
+                        labels.push(label);
+                    }
                 }
+                None => merged.push((env.clone(), vec![label])),
             }
-            None => {}
         }
     }
+    merged
+}
+
+/// Implements the structure_errors function which takes a given file and returns everything
+/// that is wrong with the shape of it as a playbook, without panicking. An empty list means
+/// the file is a playbook:
+/// * the file is readable yaml
+/// * the top level is a non empty list of plays
+/// * every play fits the playbook model, see `playbook::Play` and `playbook::ImportPlaybook`
+/// * every play has `hosts`
+pub fn structure_errors(path: &Path) -> Vec<String> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => return vec![format!("not readable: {}", err)],
+    };
+    let plays = match serde_yaml::from_str::<serde_yaml::Value>(&contents) {
+        Ok(serde_yaml::Value::Sequence(plays)) => plays,
+        Ok(serde_yaml::Value::Null) => return vec!["is empty".to_owned()],
+        Ok(_) => return vec!["is not a list of plays".to_owned()],
+        Err(err) => return vec![format!("is not valid yaml: {}", err)],
+    };
+    if plays.is_empty() {
+        return vec!["has no plays".to_owned()];
+    }
+
+    let mut errors = Vec::new();
+    for (i, play) in plays.into_iter().enumerate() {
+        let play_number = i + 1;
+        let entry_errors = playbook::entry_errors(&play);
+        if !entry_errors.is_empty() {
+            for error in entry_errors {
+                errors.push(format!("play {}: {}", play_number, error));
+            }
+            continue;
+        }
+        if let Ok(PlaybookEntry::Play(play)) = serde_yaml::from_value::<PlaybookEntry>(play) {
+            if play.hosts.is_empty() {
+                errors.push(format!("play {} has no hosts", play_number));
+            }
+        }
+    }
+    errors
+}
+
+/// Implements the unwrap_deps function which takes a given file and returns the playbooks
+/// it depends on. These are read from `# depends_on:` comments at the top of the file
+///
+/// e.g. `# depends_on: install_ior.yaml, install_mdtest.yaml`
+///
+/// # Errors
+/// Returns an error if the file path is not valid
+pub fn unwrap_deps(path: &PathBuf) -> Result<Vec<String>, Error> {
+    let mut result = Vec::new();
+    for deps in header_values(path, "depends_on")? {
+        result.extend(
+            deps.split(',')
+                .map(str::trim)
+                .filter(|dep| !dep.is_empty())
+                .map(str::to_string),
+        );
+    }
     Ok(result)
 }
 
-/// Implement error when file is not found
-/// Will throw a panic
-fn file_not_found(path: &PathBuf) -> ! {
-    panic!(
-        "File {} does not exist or is not readable",
-        path.to_str().unwrap()
-    )
+/// Implements the unwrap_timeout function which takes a given file and returns how long
+/// it is allowed to run for, if it says. This is read from a `# timeout:` comment at the
+/// top of the file
+///
+/// e.g. `# timeout: 30m`
+///
+/// # Errors
+/// Returns an error if the file path is not valid
+/// Returns an error if the timeout is not a valid duration
+pub fn unwrap_timeout(path: &PathBuf) -> Result<Option<Duration>, Error> {
+    match unwrap_header(path, "timeout")? {
+        Some(timeout) => match humantime::parse_duration(&timeout) {
+            Ok(timeout) => Ok(Some(timeout)),
+            Err(err) => Err(Error::InvalidHeader {
+                path: path.clone(),
+                key: "timeout".to_owned(),
+                message: err.to_string(),
+            }),
+        },
+        None => Ok(None),
+    }
 }
 
-/// Implement error when file is not parsable
-/// Will throw a panic
-fn file_not_parsable(path: &PathBuf) -> ! {
-    panic!("File {} is not parsable", path.to_str().unwrap())
+/// Implements the unwrap_header function which takes a given file and returns the value of
+/// the last `# key: value` comment with the given key at the top of the file
+///
+/// # Errors
+/// Returns an error if the file path is not valid
+pub fn unwrap_header(path: &PathBuf, key: &str) -> Result<Option<String>, Error> {
+    Ok(header_values(path, key)?.pop())
+}
+
+/// Gives the values of every `# key: value` comment at the top of the file, before
+/// the first line of yaml
+fn header_values(path: &PathBuf, key: &str) -> Result<Vec<String>, Error> {
+    let contents = contents(path)?;
+    let mut result = Vec::new();
+    for line in contents.lines().map(str::trim) {
+        if line.is_empty() || line == "---" {
+            continue;
+        }
+        let Some(comment) = line.strip_prefix('#') else {
+            break;
+        };
+        if let Some((name, value)) = comment.split_once(':') {
+            if name.trim() == key {
+                result.push(value.trim().to_string());
+            }
+        }
+    }
+    Ok(result)
 }
@@ -0,0 +1,927 @@
+use crate::utilities::jinja::{self, VarRef};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_yaml::{Mapping, Value};
+
+/// The keys of a task that are not its action, see `Task::action`
+const TASK_KEYWORDS: [&str; 41] = [
+    "name",
+    "when",
+    "loop",
+    "tags",
+    "become",
+    "vars",
+    "register",
+    "notify",
+    "listen",
+    "args",
+    "async",
+    "poll",
+    "any_errors_fatal",
+    "become_exe",
+    "become_flags",
+    "become_method",
+    "become_user",
+    "changed_when",
+    "check_mode",
+    "collections",
+    "connection",
+    "debugger",
+    "delay",
+    "delegate_facts",
+    "delegate_to",
+    "diff",
+    "environment",
+    "failed_when",
+    "ignore_errors",
+    "ignore_unreachable",
+    "local_action",
+    "loop_control",
+    "module_defaults",
+    "no_log",
+    "port",
+    "remote_user",
+    "retries",
+    "run_once",
+    "throttle",
+    "timeout",
+    "until",
+];
+
+/// Task keywords that are bare jinja expressions instead of templates, like `when`
+const CONDITIONALS: [&str; 3] = ["changed_when", "failed_when", "until"];
+
+/// The names of the template action, see `Task::action`
+const TEMPLATE_ACTIONS: [&str; 3] = [
+    "template",
+    "ansible.builtin.template",
+    "ansible.legacy.template",
+];
+
+/// The names of the set_fact action, see `Task::action`
+const SET_FACT_ACTIONS: [&str; 3] = [
+    "set_fact",
+    "ansible.builtin.set_fact",
+    "ansible.legacy.set_fact",
+];
+
+/// A way one file pulls in another, see `Task::include` and `ImportPlaybook`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IncludeKind {
+    ImportPlaybook,
+    ImportTasks,
+    IncludeTasks,
+    ImportRole,
+    IncludeRole,
+}
+
+impl std::fmt::Display for IncludeKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let keyword = match self {
+            IncludeKind::ImportPlaybook => "import_playbook",
+            IncludeKind::ImportTasks => "import_tasks",
+            IncludeKind::IncludeTasks => "include_tasks",
+            IncludeKind::ImportRole => "import_role",
+            IncludeKind::IncludeRole => "include_role",
+        };
+        write!(f, "{}", keyword)
+    }
+}
+
+/// A task that pulls in a tasks file or a role
+#[derive(Clone, Debug, PartialEq)]
+pub struct TaskInclude {
+    pub kind: IncludeKind,
+    /// The tasks file, or the name of the role, as it was written
+    pub target: String,
+    /// The tasks file of the role to run instead of `main`, for roles
+    pub tasks_from: Option<String>,
+}
+
+/// A playbook, the list of plays and `import_playbook`s in a playbook file
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Playbook {
+    pub entries: Vec<PlaybookEntry>,
+}
+
+/// A single entry of a playbook
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged, try_from = "Value")]
+pub enum PlaybookEntry {
+    Import(ImportPlaybook),
+    Play(Play),
+}
+
+/// An entry of a playbook that runs the plays of another playbook file
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ImportPlaybook {
+    #[serde(alias = "ansible.builtin.import_playbook")]
+    pub import_playbook: String,
+    #[serde(
+        default,
+        deserialize_with = "text",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub name: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub when: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tags: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Mapping::is_empty"
+    )]
+    pub vars: Mapping,
+    /// Every key that is not modelled, as it was written
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// A single play, which runs tasks and roles against `hosts`
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Play {
+    #[serde(
+        default,
+        deserialize_with = "text",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub name: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub hosts: Vec<String>,
+    #[serde(rename = "become", default, skip_serializing_if = "Option::is_none")]
+    pub become_root: Option<Flag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gather_facts: Option<Flag>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Mapping::is_empty"
+    )]
+    pub vars: Mapping,
+    /// Every entry is a list of files, where ansible loads the first one that is found, e.g.
+    /// `- [vars/{{ os }}.yml, vars/default.yml]`. A single file is a list of one
+    #[serde(
+        default,
+        deserialize_with = "alternatives",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub vars_files: Vec<Vec<String>>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub pre_tasks: Vec<TaskEntry>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tasks: Vec<TaskEntry>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub post_tasks: Vec<TaskEntry>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub handlers: Vec<Handler>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub roles: Vec<RoleRef>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tags: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub when: Vec<String>,
+    /// Every key that is not modelled, as it was written
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// A single entry of a list of tasks
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged, try_from = "Value")]
+pub enum TaskEntry {
+    Block(Block),
+    Task(Task),
+}
+
+/// A group of tasks that share keywords, with tasks to run if one of them fails (`rescue`)
+/// and tasks that always run after them (`always`)
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Block {
+    #[serde(
+        default,
+        deserialize_with = "text",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub name: Option<String>,
+    pub block: Vec<TaskEntry>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub rescue: Vec<TaskEntry>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub always: Vec<TaskEntry>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub when: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tags: Vec<String>,
+    #[serde(rename = "become", default, skip_serializing_if = "Option::is_none")]
+    pub become_root: Option<Flag>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Mapping::is_empty"
+    )]
+    pub vars: Mapping,
+    /// Every key that is not modelled, as it was written
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// A single task, which runs an action (a module) with its arguments
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Task {
+    #[serde(
+        default,
+        deserialize_with = "text",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub name: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub when: Vec<String>,
+    #[serde(rename = "loop", default, skip_serializing_if = "Option::is_none")]
+    pub loop_items: Option<Value>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tags: Vec<String>,
+    #[serde(rename = "become", default, skip_serializing_if = "Option::is_none")]
+    pub become_root: Option<Flag>,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Mapping::is_empty"
+    )]
+    pub vars: Mapping,
+    #[serde(
+        default,
+        deserialize_with = "text",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub register: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub notify: Vec<String>,
+    /// The action and every other key that is not modelled, as it was written
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// A task that only runs when another task notifies it, by its name or a topic it listens to
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Handler {
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub listen: Vec<String>,
+    #[serde(flatten)]
+    pub task: Task,
+}
+
+/// A role a play runs, either just its name or its name with keywords
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(untagged, try_from = "Value")]
+pub enum RoleRef {
+    Name(String),
+    Role(Box<Role>),
+}
+
+/// A role a play runs, with keywords
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Role {
+    #[serde(alias = "name")]
+    pub role: String,
+    #[serde(
+        default,
+        deserialize_with = "or_default",
+        skip_serializing_if = "Mapping::is_empty"
+    )]
+    pub vars: Mapping,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub when: Vec<String>,
+    #[serde(
+        default,
+        deserialize_with = "list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub tags: Vec<String>,
+    #[serde(rename = "become", default, skip_serializing_if = "Option::is_none")]
+    pub become_root: Option<Flag>,
+    /// The role parameters and every other key that is not modelled, as they were written
+    #[serde(flatten)]
+    pub extra: Mapping,
+}
+
+/// A keyword that is either set or not, which can also be a jinja expression that decides it
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Flag {
+    Bool(bool),
+    Template(String),
+}
+
+impl Playbook {
+    /// Gives every play of the playbook, leaving out the `import_playbook`s
+    pub fn plays(&self) -> impl Iterator<Item = &Play> {
+        self.entries.iter().filter_map(|entry| match entry {
+            PlaybookEntry::Play(play) => Some(play),
+            PlaybookEntry::Import(_) => None,
+        })
+    }
+}
+
+impl PlaybookEntry {
+    /// Gives the name of the play or `import_playbook`
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            PlaybookEntry::Play(play) => play.name.as_deref(),
+            PlaybookEntry::Import(import) => import.name.as_deref(),
+        }
+    }
+
+    /// Gives every variable the entry refers to that it does not set itself, see
+    /// `Play::var_refs`
+    pub fn var_refs(
+        &self,
+        read: &dyn Fn(&str) -> Option<String>,
+        included: &mut dyn FnMut() -> Vec<TaskEntry>,
+    ) -> Vec<VarRef> {
+        let mut walk = RefWalk::new(read, included);
+        match self {
+            PlaybookEntry::Play(play) => walk.play(play),
+            PlaybookEntry::Import(import) => {
+                let guards = walk.conditions(&import.when, &[]);
+                walk.text(&import.import_playbook, &guards);
+                walk.vars(&import.vars, &guards);
+                walk.values(&import.extra, &guards);
+            }
+        }
+        walk.finish()
+    }
+}
+
+impl Play {
+    /// Implements the var refs function, which gives every variable the play refers to in
+    /// jinja, in the order they are first referred to: in its keywords, its `vars`, its roles,
+    /// the arguments and conditions of every task and handler, and the files its `template`
+    /// tasks render. `read` reads a file from the playbook dir, e.g. `templates/motd.j2`
+    ///
+    /// The tasks a task pulls in with `import_tasks`, `include_tasks`, `import_role` or
+    /// `include_role` are walked as if they were in its place, under its `when`. `included`
+    /// gives them, and is called once for every such task in the order they are walked, which
+    /// is the order `includes::playbook_tree` follows them in
+    ///
+    /// A variable is optional (see `jinja::VarRef`) when every place that refers to it has a
+    /// `default` filter, or checks it with `is defined`, or is in a task, block or play whose
+    /// `when` does. Variables the play sets itself are left out: its `vars` and `vars_files`,
+    /// `vars_prompt`, the `vars` of its roles, blocks and tasks, `register`, `set_fact` and
+    /// `loop_control.loop_var`, in the play itself or in the tasks it pulls in
+    pub fn var_refs(
+        &self,
+        read: &dyn Fn(&str) -> Option<String>,
+        included: &mut dyn FnMut() -> Vec<TaskEntry>,
+    ) -> Vec<VarRef> {
+        let mut walk = RefWalk::new(read, included);
+        walk.play(self);
+        walk.finish()
+    }
+
+    /// Gives every task of the play in the order they run: `pre_tasks`, `tasks` and then
+    /// `post_tasks`, with the tasks inside blocks in place of the blocks. Handlers are left out
+    pub fn all_tasks(&self) -> Vec<&Task> {
+        let mut tasks = Vec::new();
+        for entries in [&self.pre_tasks, &self.tasks, &self.post_tasks] {
+            collect_tasks(entries, &mut tasks);
+        }
+        tasks
+    }
+}
+
+impl Block {
+    /// Gives every task of the block, including the tasks in `rescue` and `always`, with
+    /// the tasks inside nested blocks in place of the blocks
+    pub fn all_tasks(&self) -> Vec<&Task> {
+        let mut tasks = Vec::new();
+        for entries in [&self.block, &self.rescue, &self.always] {
+            collect_tasks(entries, &mut tasks);
+        }
+        tasks
+    }
+}
+
+impl Task {
+    /// Gives the action (module) the task runs and its arguments, which is the first key
+    /// that is not a task keyword, e.g. `("ansible.builtin.copy", {src: a, dest: b})`
+    pub fn action(&self) -> Option<(&str, &Value)> {
+        self.extra.iter().find_map(|(key, value)| {
+            let key = key.as_str()?;
+            (!TASK_KEYWORDS.contains(&key) && !key.starts_with("with_")).then_some((key, value))
+        })
+    }
+
+    /// Gives the tasks file or role the task pulls in, if it is an `import_tasks`,
+    /// `include_tasks`, `import_role` or `include_role`, e.g. `include_tasks: setup.yaml`,
+    /// `include_tasks: {file: setup.yaml}` or `include_role: {name: nginx}`
+    pub fn include(&self) -> Option<TaskInclude> {
+        let (action, args) = self.action()?;
+        let kind = match action.rsplit('.').next()? {
+            "import_tasks" => IncludeKind::ImportTasks,
+            "include_tasks" => IncludeKind::IncludeTasks,
+            "import_role" => IncludeKind::ImportRole,
+            "include_role" => IncludeKind::IncludeRole,
+            _ => return None,
+        };
+        let key = match kind {
+            IncludeKind::ImportRole | IncludeKind::IncludeRole => "name",
+            _ => "file",
+        };
+        let target = match args {
+            Value::Mapping(_) => args.get(key).and_then(scalar_text),
+            args => scalar_text(args),
+        };
+        let target = target.or_else(|| self.extra.get("args")?.get(key).and_then(scalar_text))?;
+        Some(TaskInclude {
+            kind,
+            target,
+            tasks_from: args.get("tasks_from").and_then(scalar_text),
+        })
+    }
+}
+
+impl RoleRef {
+    /// Gives the name (or path) of the role
+    pub fn name(&self) -> &str {
+        match self {
+            RoleRef::Name(name) => name,
+            RoleRef::Role(role) => &role.role,
+        }
+    }
+}
+
+impl TryFrom<Value> for PlaybookEntry {
+    type Error = serde_yaml::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        let is_import = ["import_playbook", "ansible.builtin.import_playbook"]
+            .iter()
+            .any(|key| value.get(key).is_some());
+        if is_import {
+            Ok(PlaybookEntry::Import(serde_yaml::from_value(value)?))
+        } else {
+            Ok(PlaybookEntry::Play(serde_yaml::from_value(value)?))
+        }
+    }
+}
+
+impl TryFrom<Value> for TaskEntry {
+    type Error = serde_yaml::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        if value.get("block").is_some() {
+            Ok(TaskEntry::Block(serde_yaml::from_value(value)?))
+        } else {
+            Ok(TaskEntry::Task(serde_yaml::from_value(value)?))
+        }
+    }
+}
+
+impl TryFrom<Value> for RoleRef {
+    type Error = serde_yaml::Error;
+
+    fn try_from(value: Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::String(name) => Ok(RoleRef::Name(name)),
+            value => Ok(RoleRef::Role(Box::new(serde_yaml::from_value(value)?))),
+        }
+    }
+}
+
+/// Collects the variables a play refers to, see `Play::var_refs`
+struct RefWalk<'a> {
+    read: &'a dyn Fn(&str) -> Option<String>,
+    /// Gives the tasks the next include pulls in, see `Play::var_refs`
+    included: &'a mut dyn FnMut() -> Vec<TaskEntry>,
+    refs: Vec<VarRef>,
+    /// The variables the play sets itself
+    defined: Vec<String>,
+}
+
+impl<'a> RefWalk<'a> {
+    fn new(
+        read: &'a dyn Fn(&str) -> Option<String>,
+        included: &'a mut dyn FnMut() -> Vec<TaskEntry>,
+    ) -> RefWalk<'a> {
+        RefWalk {
+            read,
+            included,
+            refs: Vec::new(),
+            defined: Vec::new(),
+        }
+    }
+
+    /// Gives the variables that were referred to, without the ones that were set
+    fn finish(self) -> Vec<VarRef> {
+        let defined = self.defined;
+        self.refs
+            .into_iter()
+            .filter(|var| !defined.contains(&var.name))
+            .collect()
+    }
+
+    fn add(&mut self, refs: Vec<VarRef>, guards: &[String]) {
+        for mut var in refs {
+            var.optional |= guards.contains(&var.name);
+            jinja::add_ref(&mut self.refs, var);
+        }
+    }
+
+    fn text(&mut self, text: &str, guards: &[String]) {
+        self.add(jinja::template_refs(text), guards);
+    }
+
+    /// Walks every string in a value, keys left out
+    fn value(&mut self, value: &Value, guards: &[String]) {
+        match value {
+            Value::String(text) => self.text(text, guards),
+            Value::Sequence(items) => items.iter().for_each(|item| self.value(item, guards)),
+            Value::Mapping(mapping) => self.values(mapping, guards),
+            Value::Tagged(tagged) => self.value(&tagged.value, guards),
+            _ => {}
+        }
+    }
+
+    fn values(&mut self, mapping: &Mapping, guards: &[String]) {
+        mapping.values().for_each(|value| self.value(value, guards));
+    }
+
+    /// Walks a `vars` keyword, whose keys the play sets
+    fn vars(&mut self, vars: &Mapping, guards: &[String]) {
+        self.define(vars);
+        self.values(vars, guards);
+    }
+
+    fn define(&mut self, vars: &Mapping) {
+        let names = vars.keys().filter_map(Value::as_str).map(str::to_owned);
+        self.defined.extend(names);
+    }
+
+    /// Walks a `when` and gives the guards of whatever it decides on, which are `guards` and
+    /// the variables it checks with `is defined`
+    fn conditions(&mut self, conditions: &[String], guards: &[String]) -> Vec<String> {
+        let mut guards = guards.to_vec();
+        for condition in conditions {
+            guards.extend(jinja::guards(condition));
+        }
+        for condition in conditions {
+            self.add(jinja::expression_refs(condition), &guards);
+        }
+        guards
+    }
+
+    fn play(&mut self, play: &Play) {
+        let guards = self.conditions(&play.when, &[]);
+        self.vars(&play.vars, &guards);
+        // Which of the alternatives is loaded is only known when the playbook runs, so all
+        // of them count
+        for file in play.vars_files.iter().flatten() {
+            self.text(file, &guards);
+            let vars = (self.read)(file)
+                .and_then(|contents| serde_yaml::from_str::<Mapping>(&contents).ok());
+            if let Some(vars) = vars {
+                self.define(&vars);
+            }
+        }
+        if let Some(Value::Sequence(prompts)) = play.extra.get("vars_prompt") {
+            let names = prompts.iter().filter_map(|prompt| prompt.get("name"));
+            self.defined
+                .extend(names.filter_map(Value::as_str).map(str::to_owned));
+        }
+        for text in play.name.iter().chain(&play.hosts) {
+            self.text(text, &guards);
+        }
+        for flag in play.become_root.iter().chain(&play.gather_facts) {
+            if let Flag::Template(text) = flag {
+                self.text(text, &guards);
+            }
+        }
+        for (key, value) in &play.extra {
+            if key.as_str() != Some("vars_prompt") {
+                self.value(value, &guards);
+            }
+        }
+        for role in &play.roles {
+            match role {
+                RoleRef::Name(name) => self.text(name, &guards),
+                RoleRef::Role(role) => {
+                    let guards = self.conditions(&role.when, &guards);
+                    self.text(&role.role, &guards);
+                    self.vars(&role.vars, &guards);
+                    self.values(&role.extra, &guards);
+                }
+            }
+        }
+        for entries in [&play.pre_tasks, &play.tasks, &play.post_tasks] {
+            self.entries(entries, &guards);
+        }
+        for handler in &play.handlers {
+            self.task(&handler.task, &guards);
+        }
+    }
+
+    fn entries(&mut self, entries: &[TaskEntry], guards: &[String]) {
+        for entry in entries {
+            match entry {
+                TaskEntry::Task(task) => self.task(task, guards),
+                TaskEntry::Block(block) => {
+                    let guards = self.conditions(&block.when, guards);
+                    if let Some(name) = &block.name {
+                        self.text(name, &guards);
+                    }
+                    self.vars(&block.vars, &guards);
+                    self.values(&block.extra, &guards);
+                    for entries in [&block.block, &block.rescue, &block.always] {
+                        self.entries(entries, &guards);
+                    }
+                }
+            }
+        }
+    }
+
+    fn task(&mut self, task: &Task, guards: &[String]) {
+        let guards = self.conditions(&task.when, guards);
+        if let Some(name) = &task.name {
+            self.text(name, &guards);
+        }
+        if let Some(loop_items) = &task.loop_items {
+            self.value(loop_items, &guards);
+        }
+        if let Some(Flag::Template(text)) = &task.become_root {
+            self.text(text, &guards);
+        }
+        self.vars(&task.vars, &guards);
+        self.defined.extend(task.register.iter().cloned());
+        for text in &task.notify {
+            self.text(text, &guards);
+        }
+        let action = task.action();
+        for (key, value) in &task.extra {
+            let key = key.as_str().unwrap_or_default();
+            if CONDITIONALS.contains(&key) {
+                let conditions: Vec<String> = match value {
+                    Value::Sequence(items) => items.iter().filter_map(scalar_text).collect(),
+                    value => scalar_text(value).into_iter().collect(),
+                };
+                self.conditions(&conditions, &guards);
+                continue;
+            }
+            if key == "loop_control" {
+                if let Some(Value::String(name)) = value.get("loop_var") {
+                    self.defined.push(name.clone());
+                }
+            }
+            if action.is_some_and(|(name, _)| name == key) {
+                if SET_FACT_ACTIONS.contains(&key) {
+                    if let Value::Mapping(facts) = value {
+                        self.define(facts);
+                    }
+                }
+                if TEMPLATE_ACTIONS.contains(&key) {
+                    self.template(value, task, &guards);
+                }
+            }
+            self.value(value, &guards);
+        }
+        if task.include().is_some() {
+            let entries = (self.included)();
+            self.entries(&entries, &guards);
+        }
+    }
+
+    /// Walks the file a `template` task renders, which is looked for in `templates/` next to
+    /// the playbook and then next to the playbook itself
+    fn template(&mut self, args: &Value, task: &Task, guards: &[String]) {
+        let src = match args {
+            Value::Mapping(args) => args.get("src").and_then(Value::as_str),
+            Value::String(args) => args
+                .split_whitespace()
+                .find_map(|arg| arg.strip_prefix("src=")),
+            _ => None,
+        };
+        let src = src.or_else(|| {
+            task.extra
+                .get("args")
+                .and_then(|args| args.get("src"))
+                .and_then(Value::as_str)
+        });
+        let Some(src) = src.filter(|src| !src.contains("{{")) else {
+            return;
+        };
+        let contents = (self.read)(&format!("templates/{}", src)).or_else(|| (self.read)(src));
+        if let Some(contents) = contents {
+            self.text(&contents, guards);
+        }
+    }
+}
+
+/// Implements the entry errors function, which gives what is wrong with a single entry of a
+/// playbook that does not fit the model, naming the keyword and list entry that is wrong,
+/// e.g. `tasks entry 2: invalid type: string "x", expected a map`. An empty list means the
+/// entry fits
+pub fn entry_errors(entry: &Value) -> Vec<String> {
+    let Some(fields) = entry.as_mapping() else {
+        return vec!["not a mapping".to_owned()];
+    };
+    if let Err(err) = serde_yaml::from_value::<PlaybookEntry>(entry.clone()) {
+        let mut errors = Vec::new();
+        for (key, value) in fields {
+            let key = key.as_str().unwrap_or_default();
+            let error = match key {
+                "name" => text(value.clone()).err().map(|err| err.to_string()),
+                "hosts" | "tags" | "when" => list(value.clone()).err().map(|err| err.to_string()),
+                "vars_files" => alternatives(value.clone()).err().map(|err| err.to_string()),
+                "import_playbook" | "ansible.builtin.import_playbook" => check::<String>(value),
+                "become" | "gather_facts" => check::<Option<Flag>>(value),
+                "vars" => check::<Option<Mapping>>(value),
+                "pre_tasks" | "tasks" | "post_tasks" => check_list::<TaskEntry>(value),
+                "handlers" => check_list::<Handler>(value),
+                "roles" => check_list::<RoleRef>(value),
+                _ => None,
+            };
+            if let Some(error) = error {
+                errors.push(format!("{}: {}", key, error));
+            }
+        }
+        if errors.is_empty() {
+            errors.push(err.to_string());
+        }
+        return errors;
+    }
+    Vec::new()
+}
+
+/// Gives why the value does not fit `T`, if it does not
+fn check<T: serde::de::DeserializeOwned>(value: &Value) -> Option<String> {
+    serde_yaml::from_value::<T>(value.clone())
+        .err()
+        .map(|err| err.to_string())
+}
+
+/// Gives why the value is not a list of `T`, naming the first entry that does not fit
+fn check_list<T: serde::de::DeserializeOwned>(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::Sequence(entries) => entries.iter().enumerate().find_map(|(i, entry)| {
+            check::<T>(entry).map(|err| format!("entry {}: {}", i + 1, err))
+        }),
+        _ => check::<Vec<T>>(value),
+    }
+}
+
+/// Adds the tasks of a list of tasks to `tasks`, with the tasks inside blocks in place of
+/// the blocks
+fn collect_tasks<'a>(entries: &'a [TaskEntry], tasks: &mut Vec<&'a Task>) {
+    for entry in entries {
+        match entry {
+            TaskEntry::Task(task) => tasks.push(task),
+            TaskEntry::Block(block) => tasks.extend(block.all_tasks()),
+        }
+    }
+}
+
+/// Reads `vars_files`, which is a file or a list of entries that are each a file or a list of
+/// files to pick the first found of
+fn alternatives<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<String>>, D::Error> {
+    let error = || serde::de::Error::custom("expected a list of files or lists of files");
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(Vec::new()),
+        Value::Sequence(entries) => entries
+            .iter()
+            .map(|entry| match entry {
+                Value::Sequence(files) => files
+                    .iter()
+                    .map(|file| scalar_text(file).ok_or_else(error))
+                    .collect(),
+                entry => scalar_text(entry).map(|file| vec![file]).ok_or_else(error),
+            })
+            .collect(),
+        value => scalar_text(&value)
+            .map(|file| vec![vec![file]])
+            .ok_or_else(error),
+    }
+}
+
+/// Reads a keyword that is text, where numbers and bools are taken as text too
+fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    let value = Value::deserialize(deserializer)?;
+    match scalar_text(&value) {
+        Some(text) => Ok(Some(text)),
+        None if value.is_null() => Ok(None),
+        None => Err(serde::de::Error::custom("expected text")),
+    }
+}
+
+/// Reads a keyword that is either a single value or a list of them, e.g. `hosts: web` or
+/// `hosts: [web, db]`
+fn list<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Null => Ok(Vec::new()),
+        Value::Sequence(items) => items
+            .iter()
+            .map(|item| {
+                scalar_text(item).ok_or_else(|| serde::de::Error::custom("expected a list of text"))
+            })
+            .collect(),
+        value => scalar_text(&value)
+            .map(|text| vec![text])
+            .ok_or_else(|| serde::de::Error::custom("expected text or a list of text")),
+    }
+}
+
+/// Reads a keyword that can be left empty, e.g. `vars:` with nothing under it
+fn or_default<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Implements the scalar text function, which gives a yaml scalar as text, where numbers and
+/// bools are written the way yaml has them. Lists, mappings and null give None
+pub fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
@@ -0,0 +1,149 @@
+use crate::utilities::vars::{self, ExtraVars};
+use crate::utilities::{validate, BookEnvs};
+use serde_json::Value;
+use std::collections::HashMap as Map;
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::mem::MaybeUninit;
+use std::path::Path;
+
+/// Implements the prompt missing function, which asks on the terminal for every variable a
+/// playbook needs but was not given (see `validate::missing_vars`) and adds the answers to
+/// its extra vars. A variable that several playbooks are missing is only asked for once.
+/// Answers are typed the same way as inline `KEY=VALUE` vars
+///
+/// Gives the answers that were added to every playbook, keyed by playbook file name
+///
+/// # Errors
+/// Returns an error if a playbook is not found
+/// Returns an error if a playbook is not parsable
+/// Returns an error if the terminal cannot be read
+/// Returns an error if an answer is not a valid value
+pub fn prompt_missing(
+    books: &mut BookEnvs,
+    playbook_dir: &Path,
+    inventory: &Path,
+) -> Result<Map<String, ExtraVars>, Box<dyn std::error::Error>> {
+    let missing = validate::missing_vars(books, playbook_dir, inventory)?;
+    let mut indexes: Vec<&u64> = missing.keys().collect();
+    indexes.sort();
+
+    let mut answers: ExtraVars = Map::new();
+    let mut added: Map<String, ExtraVars> = Map::new();
+    for i in indexes {
+        let (book, book_vars, _) = books.get_mut(i).ok_or("Playbook is not selected")?;
+        for var in &missing[i] {
+            let name = &var.name;
+            let value = match answers.get(name) {
+                Some(value) => value.clone(),
+                None => {
+                    let answer = ask(
+                        &format!("{}: {} needs {}: ", i, book, var),
+                        vars::is_secret(name),
+                    )?;
+                    let (_, value) = vars::parse_pair(&format!("{}={}", name, answer))?;
+                    answers.insert(name.clone(), value.clone());
+                    value
+                }
+            };
+            book_vars.insert(name.clone(), value.clone());
+            added
+                .entry(book.clone())
+                .or_default()
+                .insert(name.clone(), value);
+        }
+    }
+    Ok(added)
+}
+
+/// Implements the save answers function, which adds the answers from `prompt_missing` to the
+/// env variable of every playbook in the env file, so they are not asked for next time.
+/// Other lines of the file are kept as they are
+///
+/// # Errors
+/// Returns an error if the env file cannot be written
+pub fn save_answers(
+    env_file: &Path,
+    answers: &Map<String, ExtraVars>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = fs::read_to_string(env_file).unwrap_or_default();
+    let mut lines: Vec<String> = contents.lines().map(str::to_string).collect();
+
+    let mut books: Vec<&String> = answers.keys().collect();
+    books.sort();
+    for book in books {
+        let mut pairs = Vec::new();
+        if let Ok(existing) = env::var(book) {
+            pairs.push(existing);
+        }
+        let mut book_answers: Vec<(&String, &Value)> = answers[book].iter().collect();
+        book_answers.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, value) in book_answers {
+            pairs.push(vars::format_pair(name, value));
+        }
+        let line = format!("{}=\"{}\"", book, escape_env(&pairs.join(",")));
+
+        let existing = lines.iter().position(|line| {
+            let line = line.trim_start();
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            line.split_once('=')
+                .map(|(key, _)| key.trim() == book)
+                .unwrap_or(false)
+        });
+        match existing {
+            Some(existing) => lines[existing] = line,
+            None => lines.push(line),
+        }
+    }
+
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    fs::write(env_file, contents)?;
+    Ok(())
+}
+
+/// Prints the prompt and reads a line from the terminal, without showing what is typed if
+/// `hidden` is set
+fn ask(prompt: &str, hidden: bool) -> io::Result<String> {
+    print!("{}", prompt);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if !hidden {
+        io::stdin().read_line(&mut answer)?;
+        return Ok(answer.trim_end_matches(['\n', '\r']).to_owned());
+    }
+
+    let fd = libc::STDIN_FILENO;
+    let mut term = MaybeUninit::<libc::termios>::uninit();
+    // SAFETY: tcgetattr fills in the termios struct when it returns 0
+    if unsafe { libc::tcgetattr(fd, term.as_mut_ptr()) } != 0 {
+        io::stdin().read_line(&mut answer)?;
+        return Ok(answer.trim_end_matches(['\n', '\r']).to_owned());
+    }
+    // SAFETY: tcgetattr succeeded above
+    let original = unsafe { term.assume_init() };
+    let mut silent = original;
+    silent.c_lflag &= !libc::ECHO;
+    // SAFETY: only the echo flag of the settings that were just read is changed
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &silent) };
+    let read = io::stdin().read_line(&mut answer);
+    // SAFETY: puts back the settings the terminal had before
+    unsafe { libc::tcsetattr(fd, libc::TCSANOW, &original) };
+    println!();
+    read?;
+    Ok(answer.trim_end_matches(['\n', '\r']).to_owned())
+}
+
+/// Escapes a value so it can be put in double quotes in an env file
+fn escape_env(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '\\' | '"' | '$') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
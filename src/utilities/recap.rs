@@ -0,0 +1,184 @@
+use serde::{Deserialize, Serialize};
+
+/// The counts ansible gives for a single host in the PLAY RECAP of a playbook
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct HostStats {
+    pub host: String,
+    pub ok: u64,
+    pub changed: u64,
+    pub unreachable: u64,
+    pub failed: u64,
+    pub skipped: u64,
+    pub rescued: u64,
+    pub ignored: u64,
+}
+
+/// Implements the parse recap function, which finds the PLAY RECAP block in the output of
+/// `ansible-playbook` and gives the counts of every host in it, in the order ansible
+/// listed them. Gives nothing if the playbook never got to the recap
+///
+/// e.g. `node1 : ok=2 changed=1 unreachable=0 failed=0 skipped=0 rescued=0 ignored=0`
+pub fn parse_recap(output: &str) -> Vec<HostStats> {
+    let mut hosts = Vec::new();
+    let mut in_recap = false;
+    for line in output.lines().map(strip_colours) {
+        if line.contains("PLAY RECAP") {
+            in_recap = true;
+            hosts.clear();
+            continue;
+        }
+        if !in_recap {
+            continue;
+        }
+        match parse_host(&line) {
+            Some(stats) => hosts.push(stats),
+            None if line.trim().is_empty() => {}
+            None => in_recap = false,
+        }
+    }
+    hosts
+}
+
+/// Parses a single host line of the recap. Anything in front of the host name, like a
+/// timestamp, is ignored
+fn parse_host(line: &str) -> Option<HostStats> {
+    let (host, counts) = line.split_once(" : ")?;
+    let mut stats = HostStats {
+        host: host.split_whitespace().last()?.to_owned(),
+        ..Default::default()
+    };
+    let mut found = false;
+    for count in counts.split_whitespace() {
+        let Some((name, value)) = count.split_once('=') else {
+            continue;
+        };
+        let Ok(value) = value.parse::<u64>() else {
+            continue;
+        };
+        let field = match name {
+            "ok" => &mut stats.ok,
+            "changed" => &mut stats.changed,
+            "unreachable" => &mut stats.unreachable,
+            "failed" => &mut stats.failed,
+            "skipped" => &mut stats.skipped,
+            "rescued" => &mut stats.rescued,
+            "ignored" => &mut stats.ignored,
+            _ => continue,
+        };
+        *field = value;
+        found = true;
+    }
+    found.then_some(stats)
+}
+
+/// Implements the recap table function, which lays out the counts of every host as a table,
+/// with every line indented by `indent`
+pub fn recap_table(hosts: &[HostStats], indent: &str) -> String {
+    let width = hosts
+        .iter()
+        .map(|stats| stats.host.len())
+        .chain(std::iter::once("HOST".len()))
+        .max()
+        .unwrap_or_default();
+    let mut table = format!(
+        "{}{:<width$}  {:>3}  {:>7}  {:>11}  {:>6}  {:>7}  {:>7}  {:>7}\n",
+        indent,
+        "HOST",
+        "OK",
+        "CHANGED",
+        "UNREACHABLE",
+        "FAILED",
+        "SKIPPED",
+        "RESCUED",
+        "IGNORED",
+        width = width
+    );
+    for stats in hosts {
+        table.push_str(&format!(
+            "{}{:<width$}  {:>3}  {:>7}  {:>11}  {:>6}  {:>7}  {:>7}  {:>7}\n",
+            indent,
+            stats.host,
+            stats.ok,
+            stats.changed,
+            stats.unreachable,
+            stats.failed,
+            stats.skipped,
+            stats.rescued,
+            stats.ignored,
+            width = width
+        ));
+    }
+    table
+}
+
+/// Removes the ANSI colour codes ansible puts around its output when it thinks it is
+/// writing to a terminal
+fn strip_colours(line: &str) -> String {
+    let mut result = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(stats: &HostStats) -> [u64; 7] {
+        [
+            stats.ok,
+            stats.changed,
+            stats.unreachable,
+            stats.failed,
+            stats.skipped,
+            stats.rescued,
+            stats.ignored,
+        ]
+    }
+
+    #[test]
+    fn parse_host_reads_every_count() {
+        let stats = parse_host(
+            "node1   : ok=5 changed=2 unreachable=0 failed=1 skipped=3 rescued=1 ignored=4",
+        )
+        .unwrap();
+        assert_eq!(stats.host, "node1");
+        assert_eq!(counts(&stats), [5, 2, 0, 1, 3, 1, 4]);
+    }
+
+    #[test]
+    fn parse_host_ignores_prefixes_and_unknown_counts() {
+        let stats =
+            parse_host("2024-01-01 10:00:00 [0: site.yaml] web-1 : ok=1 failed=0 extra=9 x=y")
+                .unwrap();
+        assert_eq!(stats.host, "web-1");
+        assert_eq!(counts(&stats), [1, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn parse_host_gives_none_for_other_lines() {
+        assert!(parse_host("TASK [debug] : nothing to count").is_none());
+        assert!(parse_host("no separator here").is_none());
+    }
+
+    #[test]
+    fn parse_recap_reads_the_last_recap_only() {
+        let output = "PLAY RECAP ***\nold : ok=1\n\nPLAY [again]\nPLAY RECAP ***\n\x1b[0;32mnode1\x1b[0m : ok=2 changed=1 rescued=1 ignored=2\nnode2 : ok=0 unreachable=1\n\nSome other line\n";
+        let hosts = parse_recap(output);
+        assert_eq!(hosts.len(), 2);
+        assert_eq!(hosts[0].host, "node1");
+        assert_eq!(counts(&hosts[0]), [2, 1, 0, 0, 0, 1, 2]);
+        assert_eq!(hosts[1].host, "node2");
+        assert_eq!(counts(&hosts[1]), [0, 0, 1, 0, 0, 0, 0]);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::commands::run::{BookResult, Status};
+use crate::utilities::{callback, vars};
+use clap::ValueEnum;
+use serde_json::json;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// The machine readable formats a run can be reported in
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Junit,
+}
+
+/// Implements the write report function, which writes the results of a run to a file
+///
+/// # Errors
+/// Returns an error if the report file cannot be written
+pub fn write_report(
+    results: &[BookResult],
+    format: &ReportFormat,
+    path: &Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let report = match format {
+        ReportFormat::Json => to_json(results)?,
+        ReportFormat::Junit => to_junit(results),
+    };
+    fs::write(path, report)?;
+    Ok(())
+}
+
+/// Implements the to json function, which gives a JSON document with a record for every playbook
+///
+/// # Errors
+/// Returns an error if the report cannot be serialized
+pub fn to_json(results: &[BookResult]) -> Result<String, Box<dyn std::error::Error>> {
+    let books: Vec<_> = results
+        .iter()
+        .map(|result| {
+            json!({
+                "index": result.index,
+                "book": result.book,
+                "extra_vars": vars::redact(&result.extra_vars),
+                "options": result.options,
+                "status": result.status,
+                "attempt": result.attempt,
+                "attempts": result.attempts,
+                "start": result.start.map(timestamp),
+                "end": result.end.map(timestamp),
+                "duration_secs": result.duration().as_secs_f64(),
+                "exit_code": result.exit_code,
+                "hosts": result.hosts,
+                "tasks": result.tasks,
+                "stdout": result.stdout,
+                "stderr": result.stderr,
+            })
+        })
+        .collect();
+    Ok(serde_json::to_string_pretty(&json!({ "books": books }))?)
+}
+
+/// Implements the to junit function, which gives a JUnit XML document with a testcase for
+/// every playbook
+pub fn to_junit(results: &[BookResult]) -> String {
+    let failures = results
+        .iter()
+        .filter(|result| matches!(result.status, Status::Failed | Status::TimedOut))
+        .count();
+    let skipped = results
+        .iter()
+        .filter(|result| matches!(result.status, Status::Skipped | Status::Cancelled))
+        .count();
+    let total: Duration = results.iter().map(BookResult::duration).sum();
+
+    let mut xml = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n".to_owned();
+    xml.push_str(&format!(
+        "<testsuites>\n  <testsuite name=\"ansible-playbook\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        results.len(),
+        failures,
+        skipped,
+        total.as_secs_f64()
+    ));
+    for result in results {
+        xml.push_str(&format!(
+            "    <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\">\n",
+            escape(&format!("{}: {}", result.index, result.book)),
+            escape(&result.book),
+            result.duration().as_secs_f64()
+        ));
+        if !result.options.is_empty() {
+            xml.push_str(&format!(
+                "      <properties>\n        <property name=\"options\" value=\"{}\"/>\n      </properties>\n",
+                escape(&result.options.to_string())
+            ));
+        }
+        match result.status {
+            Status::Failed => xml.push_str(&format!(
+                "      <failure message=\"{}\">{}</failure>\n",
+                escape(&match result.exit_code {
+                    Some(0) => "a host failed or could not be reached".to_owned(),
+                    Some(code) => format!("ansible-playbook exited with code {}", code),
+                    None => "ansible-playbook did not exit".to_owned(),
+                }),
+                escape(&callback::task_failures(&result.tasks, ""))
+            )),
+            Status::TimedOut => xml.push_str(&format!(
+                "      <failure message=\"{}\"/>\n",
+                escape(&result.status.to_string())
+            )),
+            Status::Skipped | Status::Cancelled => xml.push_str(&format!(
+                "      <skipped message=\"{}\"/>\n",
+                escape(&result.status.to_string())
+            )),
+            Status::Success => {}
+        }
+        if !result.stdout.is_empty() {
+            xml.push_str(&format!(
+                "      <system-out>{}</system-out>\n",
+                escape(&result.stdout)
+            ));
+        }
+        if !result.stderr.is_empty() {
+            xml.push_str(&format!(
+                "      <system-err>{}</system-err>\n",
+                escape(&result.stderr)
+            ));
+        }
+        xml.push_str("    </testcase>\n");
+    }
+    xml.push_str("  </testsuite>\n</testsuites>\n");
+    xml
+}
+
+/// Formats a point in time as an RFC 3339 timestamp
+fn timestamp(time: SystemTime) -> String {
+    humantime::format_rfc3339_millis(time).to_string()
+}
+
+/// Escapes the characters that are not allowed in XML text and attributes, and drops the
+/// control characters (e.g. ansible's colour codes) that XML cannot hold at all
+fn escape(text: &str) -> String {
+    text.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\r' | '\t'))
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
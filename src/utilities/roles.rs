@@ -0,0 +1,280 @@
+use crate::error::Error;
+use crate::utilities::{parse, playbook};
+use serde_yaml::{Mapping, Value};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Where ansible looks for roles when `roles_path` is not set anywhere
+const DEFAULT_ROLES_PATH: [&str; 3] = [
+    "~/.ansible/roles",
+    "/usr/share/ansible/roles",
+    "/etc/ansible/roles",
+];
+
+/// The names a yaml file in a role can have, e.g. `defaults/main.yml`
+const YAML_EXTENSIONS: [&str; 3] = [".yml", ".yaml", ""];
+
+/// A variable a role takes, from its `defaults/main.yml` and its argument spec
+pub struct RoleVar {
+    pub name: String,
+    /// The type from the argument spec, or the type of the default when the argument spec
+    /// does not have the variable, e.g. `str`, `int`, `bool`, `list` or `dict`
+    pub var_type: Option<String>,
+    /// The default from the argument spec, or else from `defaults/main.yml`
+    pub default: Option<Value>,
+    /// Whether the argument spec says the variable has to be given
+    pub required: bool,
+    /// The only values the argument spec allows, if it says
+    pub choices: Vec<Value>,
+}
+
+/// A role, with the variables it takes
+pub struct RoleInfo {
+    pub name: String,
+    pub path: PathBuf,
+    pub vars: Vec<RoleVar>,
+}
+
+/// Implements the roles path function, which gives every dir roles are looked for in, in
+/// order: `roles/` next to the playbook, then the `roles_path` that is set (see
+/// `configured_roles_path`) or ansible's default one, and then the playbook dir itself
+pub fn roles_path(book_dir: &Path) -> Vec<PathBuf> {
+    let mut dirs = vec![book_dir.join("roles")];
+    match configured_roles_path(book_dir) {
+        Some(configured) => dirs.extend(configured),
+        None => dirs.extend(DEFAULT_ROLES_PATH.iter().map(|dir| expand_home(dir))),
+    }
+    dirs.push(book_dir.to_path_buf());
+    dirs
+}
+
+/// Implements the find role function, which gives the dir of the role with the given name,
+/// see `roles_path`. A name with a `/` in it is a path from the playbook dir
+pub fn find_role(name: &str, book_dir: &Path) -> Option<PathBuf> {
+    if name.contains('/') {
+        let path = book_dir.join(expand_home(name));
+        return path.is_dir().then_some(path);
+    }
+    roles_path(book_dir)
+        .into_iter()
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_dir())
+}
+
+/// Implements the role info function, which reads the variables of the role with the given
+/// name from its `defaults/main.yml` and the `main` entry point of its argument spec
+/// (`meta/argument_specs.yml`, or `argument_specs` in `meta/main.yml`). Variables are given
+/// in the order the argument spec has them, then the ones only `defaults/main.yml` has.
+/// Gives None when the role is not found
+///
+/// # Errors
+/// Returns an error if the defaults or the argument spec of the role are not parsable
+pub fn role_info(name: &str, book_dir: &Path) -> Result<Option<RoleInfo>, Error> {
+    let Some(path) = find_role(name, book_dir) else {
+        return Ok(None);
+    };
+    let defaults = match role_file(&path, "defaults/main")? {
+        Some(Value::Mapping(defaults)) => defaults,
+        _ => Mapping::new(),
+    };
+    let spec = match role_file(&path, "meta/argument_specs")? {
+        Some(spec) => Some(spec),
+        None => role_file(&path, "meta/main")?,
+    };
+    let options = spec
+        .as_ref()
+        .and_then(|spec| spec.get("argument_specs")?.get("main")?.get("options"))
+        .and_then(Value::as_mapping);
+
+    let mut vars = Vec::new();
+    for (key, option) in options.into_iter().flatten() {
+        let Some(var_name) = key.as_str() else {
+            continue;
+        };
+        let var_type = match option.get("type").and_then(Value::as_str) {
+            Some(var_type) => var_type.to_owned(),
+            // ansible takes options without a type as text
+            None => "str".to_owned(),
+        };
+        let choices = match option.get("choices") {
+            Some(Value::Sequence(choices)) => choices.clone(),
+            _ => Vec::new(),
+        };
+        vars.push(RoleVar {
+            name: var_name.to_owned(),
+            var_type: Some(var_type),
+            default: option
+                .get("default")
+                .or_else(|| defaults.get(var_name))
+                .cloned(),
+            required: option.get("required").and_then(Value::as_bool) == Some(true),
+            choices,
+        });
+    }
+    for (key, default) in &defaults {
+        let Some(var_name) = key.as_str() else {
+            continue;
+        };
+        if vars.iter().any(|var| var.name == var_name) {
+            continue;
+        }
+        vars.push(RoleVar {
+            name: var_name.to_owned(),
+            var_type: yaml_type(default).map(str::to_owned),
+            default: Some(default.clone()),
+            required: false,
+            choices: Vec::new(),
+        });
+    }
+    Ok(Some(RoleInfo {
+        name: name.to_owned(),
+        path,
+        vars,
+    }))
+}
+
+/// Implements the check var function, which gives whether an extra var fits the type and
+/// choices the argument spec of a role has for it, the way ansible converts values, e.g. a
+/// `bool` can be `yes` and an `int` can be `"8"`. Says what was expected when it does not
+pub fn check_var(var: &RoleVar, value: &serde_json::Value) -> Result<(), String> {
+    use serde_json::Value as Json;
+
+    let text = match value {
+        Json::String(text) => Some(text.trim()),
+        _ => None,
+    };
+    let fits = match var.var_type.as_deref() {
+        Some("str" | "path") => !matches!(value, Json::Array(_) | Json::Object(_) | Json::Null),
+        Some("int") => {
+            value.is_i64() || value.is_u64() || text.is_some_and(|t| t.parse::<i64>().is_ok())
+        }
+        Some("float") => value.is_number() || text.is_some_and(|t| t.parse::<f64>().is_ok()),
+        Some("bool") => match value {
+            Json::Bool(_) => true,
+            Json::Number(number) => number.as_u64().is_some_and(|number| number <= 1),
+            Json::String(_) => text.is_some_and(|t| {
+                [
+                    "yes", "no", "true", "false", "on", "off", "y", "n", "1", "0",
+                ]
+                .contains(&t.to_lowercase().as_str())
+            }),
+            _ => false,
+        },
+        // ansible splits text on commas into a list
+        Some("list") => value.is_array() || value.is_string(),
+        Some("dict") => {
+            value.is_object() || text.is_some_and(|t| t.starts_with('{') || t.contains('='))
+        }
+        _ => true,
+    };
+    if !fits {
+        return Err(format!(
+            "expected {}, got {}",
+            var.var_type.as_deref().unwrap_or_default(),
+            value
+        ));
+    }
+    if !var.choices.is_empty() {
+        let given = serde_yaml::to_value(value).unwrap_or(Value::Null);
+        let matches = var.choices.iter().any(|choice| {
+            *choice == given
+                || playbook::scalar_text(choice).is_some_and(|c| Some(c.as_str()) == text)
+        });
+        if !matches {
+            let choices: Vec<String> = var
+                .choices
+                .iter()
+                .filter_map(playbook::scalar_text)
+                .collect();
+            return Err(format!(
+                "expected one of {}, got {}",
+                choices.join(", "),
+                value
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Implements the configured roles path function, which gives the `roles_path` that is set,
+/// if any. `ANSIBLE_ROLES_PATH` comes first, then `roles_path` in the `[defaults]` of the
+/// first ansible.cfg found, the same way ansible looks for it: `ANSIBLE_CONFIG`,
+/// `ansible.cfg` in the current dir, `ansible.cfg` in the playbook dir, `~/.ansible.cfg` and
+/// `/etc/ansible/ansible.cfg`. Relative dirs in ansible.cfg are from the dir it is in
+pub fn configured_roles_path(book_dir: &Path) -> Option<Vec<PathBuf>> {
+    if let Ok(roles_path) = env::var("ANSIBLE_ROLES_PATH") {
+        return Some(split_path(&roles_path, Path::new("")));
+    }
+    let mut configs = Vec::new();
+    if let Ok(config) = env::var("ANSIBLE_CONFIG") {
+        configs.push(PathBuf::from(config));
+    }
+    configs.push(PathBuf::from("ansible.cfg"));
+    configs.push(book_dir.join("ansible.cfg"));
+    configs.push(expand_home("~/.ansible.cfg"));
+    configs.push(PathBuf::from("/etc/ansible/ansible.cfg"));
+
+    let config = configs.into_iter().find(|config| config.is_file())?;
+    let contents = std::fs::read_to_string(&config).ok()?;
+    let config_dir = config.parent().unwrap_or(Path::new(""));
+    let mut section = "";
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim();
+            continue;
+        }
+        let Some((key, value)) = line.split_once(['=', ':']) else {
+            continue;
+        };
+        if section == "defaults" && key.trim() == "roles_path" {
+            return Some(split_path(value.trim(), config_dir));
+        }
+    }
+    None
+}
+
+/// Reads a yaml file of a role, e.g. `defaults/main` for `defaults/main.yml`. Gives None
+/// when the role does not have it
+fn role_file(role: &Path, name: &str) -> Result<Option<Value>, Error> {
+    let Some(path) = YAML_EXTENSIONS
+        .iter()
+        .map(|extension| role.join(format!("{}{}", name, extension)))
+        .find(|path| path.is_file())
+    else {
+        return Ok(None);
+    };
+    parse::unwrap(&path).map(Some)
+}
+
+/// Gives the argument spec type a yaml value would have
+fn yaml_type(value: &Value) -> Option<&'static str> {
+    match value {
+        Value::String(_) => Some("str"),
+        Value::Number(number) if number.is_f64() => Some("float"),
+        Value::Number(_) => Some("int"),
+        Value::Bool(_) => Some("bool"),
+        Value::Sequence(_) => Some("list"),
+        Value::Mapping(_) => Some("dict"),
+        _ => None,
+    }
+}
+
+/// Splits a `:` separated list of dirs, with relative dirs taken from `base`
+fn split_path(dirs: &str, base: &Path) -> Vec<PathBuf> {
+    dirs.split(':')
+        .map(str::trim)
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| base.join(expand_home(dir)))
+        .collect()
+}
+
+/// Puts the home dir in place of a leading `~`
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
@@ -0,0 +1,113 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread::{self, JoinHandle};
+use std::time::SystemTime;
+
+/// How the output of a command is handled while it runs
+#[derive(Clone, Default)]
+pub struct StreamOptions {
+    /// Print every line as it arrives
+    pub echo: bool,
+    /// Put in front of every printed line, e.g. `[0: install_ior.yaml] `
+    pub prefix: Option<String>,
+    /// Put the time each line arrived in front of it when it is printed or logged
+    pub timestamps: bool,
+    /// Also write every line to this file
+    pub log: Option<PathBuf>,
+}
+
+/// Everything a command wrote, split by the pipe it came from, without prefixes or timestamps
+#[derive(Default)]
+pub struct Captured {
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Which pipe a line came from
+#[derive(Clone, Copy)]
+enum Source {
+    Stdout,
+    Stderr,
+}
+
+/// Implements the stream function, which reads stdout and stderr of a command at the same
+/// time, so neither pipe can fill up and block the command, and handles every line in the
+/// order it arrived as `options` says. The returned handle gives everything that was read
+/// once both pipes are closed
+///
+/// # Errors
+/// Returns an error if the log file cannot be created
+pub fn stream<O, E>(
+    stdout: O,
+    stderr: E,
+    options: StreamOptions,
+) -> io::Result<JoinHandle<io::Result<Captured>>>
+where
+    O: Read + Send + 'static,
+    E: Read + Send + 'static,
+{
+    let mut log = match &options.log {
+        Some(path) => Some(File::create(path)?),
+        None => None,
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let stdout_reader = read_lines(stdout, Source::Stdout, tx.clone());
+    let stderr_reader = read_lines(stderr, Source::Stderr, tx);
+
+    Ok(thread::spawn(move || {
+        let mut captured = Captured::default();
+        let prefix = options.prefix.unwrap_or_default();
+        for (source, line) in rx {
+            let shown = if options.timestamps {
+                format!(
+                    "{} {}",
+                    humantime::format_rfc3339_millis(SystemTime::now()),
+                    line
+                )
+            } else {
+                line.clone()
+            };
+            if options.echo {
+                println!("{}{}", prefix, shown);
+            }
+            if let Some(log) = &mut log {
+                writeln!(log, "{}", shown)?;
+            }
+            let output = match source {
+                Source::Stdout => &mut captured.stdout,
+                Source::Stderr => &mut captured.stderr,
+            };
+            output.push_str(&line);
+            output.push('\n');
+        }
+        stdout_reader.join().ok();
+        stderr_reader.join().ok();
+        Ok(captured)
+    }))
+}
+
+/// Sends every line of the pipe to the channel until the pipe is closed. Lines that are not
+/// valid UTF-8 are kept, with the invalid bytes replaced
+fn read_lines<R>(pipe: R, source: Source, tx: mpsc::Sender<(Source, String)>) -> JoinHandle<()>
+where
+    R: Read + Send + 'static,
+{
+    thread::spawn(move || {
+        let mut reader = BufReader::new(pipe);
+        let mut buffer = Vec::new();
+        while let Ok(read) = reader.read_until(b'\n', &mut buffer) {
+            if read == 0 {
+                break;
+            }
+            let line = String::from_utf8_lossy(&buffer);
+            let line = line.trim_end_matches(['\n', '\r']).to_owned();
+            if tx.send((source, line)).is_err() {
+                break;
+            }
+            buffer.clear();
+        }
+    })
+}
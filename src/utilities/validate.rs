@@ -0,0 +1,190 @@
+use crate::utilities::{includes, inventory, parse, roles, BookEnvs};
+use std::collections::HashMap as Map;
+use std::path::Path;
+
+/// A variable a playbook refers to but was not given
+pub struct MissingVar {
+    pub name: String,
+    /// The labels of the plays that refer to it, see `parse::PlaySummary::label`
+    pub plays: Vec<String>,
+}
+
+impl std::fmt::Display for MissingVar {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} ({})", self.name, self.plays.join(", "))
+    }
+}
+
+/// An extra var that does not fit the argument spec of a role the playbook runs
+pub struct RoleVarMismatch {
+    pub name: String,
+    pub role: String,
+    /// What the argument spec expected, see `roles::check_var`
+    pub message: String,
+}
+
+impl std::fmt::Display for RoleVarMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: {} ({})", self.name, self.message, self.role)
+    }
+}
+
+/// Implements the role var mismatches function, which checks the extra vars of every
+/// playbook against the argument specs of the roles it runs (see `roles::role_info` and
+/// `includes::role_names`), and gives the ones that do not fit. Roles that are not found,
+/// and vars the argument specs do not have, are not checked. Playbooks where everything fits
+/// are left out
+///
+/// # Errors
+/// Returns an error if a playbook is not found
+/// Returns an error if a playbook, or the defaults or argument spec of a role, is not parsable
+pub fn role_var_mismatches(
+    books: &BookEnvs,
+    playbook_dir: &Path,
+) -> Result<Map<u64, Vec<RoleVarMismatch>>, Box<dyn std::error::Error>> {
+    let mut mismatches = Map::new();
+    for (i, (book, book_vars, _)) in books {
+        if book_vars.is_empty() {
+            continue;
+        }
+        let book_path = playbook_dir.join(book);
+        let tree = includes::playbook_tree(&book_path)?;
+        let book_dir = book_path.parent().unwrap_or(playbook_dir);
+        let mut book_mismatches = Vec::new();
+        for role in includes::role_names(&tree) {
+            let Some(info) = roles::role_info(&role, book_dir)? else {
+                continue;
+            };
+            for var in &info.vars {
+                let Some(value) = book_vars.get(&var.name) else {
+                    continue;
+                };
+                if let Err(message) = roles::check_var(var, value) {
+                    book_mismatches.push(RoleVarMismatch {
+                        name: var.name.clone(),
+                        role: role.clone(),
+                        message,
+                    });
+                }
+            }
+        }
+        if !book_mismatches.is_empty() {
+            mismatches.insert(*i, book_mismatches);
+        }
+    }
+    Ok(mismatches)
+}
+
+/// Implements the role var mismatches error function, which describes every playbook with
+/// extra vars that do not fit its roles, in index order
+pub fn role_var_mismatches_error(
+    books: &BookEnvs,
+    mismatches: &Map<u64, Vec<RoleVarMismatch>>,
+) -> String {
+    let mut indexes: Vec<&u64> = mismatches.keys().collect();
+    indexes.sort();
+    let mut error = "Extra vars do not fit the argument specs of the roles:".to_owned();
+    for i in indexes {
+        error.push_str(&format!(
+            "\n    {}: {} - {}",
+            i,
+            books[i].0,
+            mismatches[i]
+                .iter()
+                .map(RoleVarMismatch::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    error
+}
+
+/// Implements the missing vars function, which gives the variables any play of every playbook
+/// requires (see `playbook::Play::var_refs`) that it was not given as an extra var, with the
+/// plays that need them. The plays of the playbooks it imports are checked as well, see
+/// `includes::flatten_plays`. Playbooks that are not missing anything are left out
+///
+/// Optional variables are not required, e.g. `{{ foo | default('x') }}` or a task with
+/// `when: foo is defined`, see `jinja::VarRef`. Neither are variables a role the playbook runs
+/// has a default for, in its `defaults/main.yml` or its argument spec, or variables the
+/// inventory sets, see `inventory::defined_vars`. Options the argument
+/// spec of a role says are required are, with `role <name>` in place of the plays
+///
+/// # Errors
+/// Returns an error if a playbook is not found
+/// Returns an error if a playbook, or the defaults or argument spec of a role, is not parsable
+pub fn missing_vars(
+    books: &BookEnvs,
+    playbook_dir: &Path,
+    inventory: &Path,
+) -> Result<Map<u64, Vec<MissingVar>>, Box<dyn std::error::Error>> {
+    let mut missing = Map::new();
+    for (i, (book, book_vars, _)) in books {
+        let mut book_missing: Vec<MissingVar> = Vec::new();
+        let book_path = playbook_dir.join(book);
+        let tree = includes::playbook_tree(&book_path)?;
+        let book_dir = book_path.parent().unwrap_or(playbook_dir);
+        let mut defaulted = inventory::defined_vars(book_dir, inventory);
+        let mut required = Vec::new();
+        for role in includes::role_names(&tree) {
+            let Some(info) = roles::role_info(&role, book_dir)? else {
+                continue;
+            };
+            for var in info.vars {
+                if var.required {
+                    required.push((var.name, format!("role {}", role)));
+                } else if var.default.is_some() {
+                    defaulted.push(var.name);
+                }
+            }
+        }
+
+        let plays = includes::flatten_plays(&tree);
+        for (var, plays) in parse::merge_envs(&plays) {
+            if var.optional || book_vars.contains_key(&var.name) || defaulted.contains(&var.name) {
+                continue;
+            }
+            book_missing.push(MissingVar {
+                name: var.name,
+                plays,
+            });
+        }
+        for (name, role) in required {
+            if book_vars.contains_key(&name) {
+                continue;
+            }
+            match book_missing.iter_mut().find(|var| var.name == name) {
+                Some(var) => var.plays.push(role),
+                None => book_missing.push(MissingVar {
+                    name,
+                    plays: vec![role],
+                }),
+            }
+        }
+        if !book_missing.is_empty() {
+            missing.insert(*i, book_missing);
+        }
+    }
+    Ok(missing)
+}
+
+/// Implements the missing vars error function, which describes every playbook that is
+/// missing variables, in index order
+pub fn missing_vars_error(books: &BookEnvs, missing: &Map<u64, Vec<MissingVar>>) -> String {
+    let mut indexes: Vec<&u64> = missing.keys().collect();
+    indexes.sort();
+    let mut error = "Playbooks are missing required variables:".to_owned();
+    for i in indexes {
+        error.push_str(&format!(
+            "\n    {}: {} - {}",
+            i,
+            books[i].0,
+            missing[i]
+                .iter()
+                .map(MissingVar::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ));
+    }
+    error
+}
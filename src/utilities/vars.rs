@@ -0,0 +1,316 @@
+use serde_json::Value;
+use std::collections::HashMap as Map;
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// The extra vars of a playbook, keyed by variable name
+pub type ExtraVars = Map<String, Value>;
+
+/// Parts of variable names that mean the value is a secret, see `is_secret`
+const SECRET_NAMES: [&str; 6] = ["pass", "secret", "token", "key", "credential", "private"];
+
+/// What the value of a secret is replaced by wherever it is written down, see `redact`
+pub const REDACTED: &str = "********";
+
+/// Implements the parse vars function, which takes a comma separated list of `KEY=VALUE` pairs
+/// and returns them as typed extra vars.
+///
+/// Values are typed like this:
+/// * numbers, `true`/`false` and `null` are kept as such, e.g. `forks=10`. Numbers that would
+///   not be sent to ansible exactly as written are strings, e.g. `ver=1.10`, `x=1e3` or `id=007`
+/// * values starting with `[` or `{` are yaml flow lists and dicts, e.g. `hosts=[a, b]`,
+///   except jinja, which is a string, e.g. `msg={{ other }}`
+/// * values in quotes are always strings, e.g. `version="010"` or `msg='a, b'`
+/// * anything else is a string, e.g. `msg=hello world`
+///
+/// Commas inside quotes or brackets do not split pairs, and a backslash outside quotes takes
+/// the next character as is, e.g. `msg=a\,b` or `path=C:\\dir`
+///
+/// # Errors
+/// Returns an error if a pair is not in the format KEY=VALUE
+/// Returns an error if a quoted, list or dict value is not valid yaml
+pub fn parse_vars(pairs: &str) -> Result<ExtraVars, Box<dyn std::error::Error>> {
+    let mut vars = Map::new();
+    for pair in split_unquoted(pairs, ',') {
+        let (key, value) = parse_pair(&pair)?;
+        vars.insert(key, value);
+    }
+    Ok(vars)
+}
+
+/// Implements the parse pair function, which takes a single `KEY=VALUE` pair and returns
+/// the key and typed value, see `parse_vars`
+///
+/// # Errors
+/// Returns an error if the pair is not in the format KEY=VALUE
+/// Returns an error if a quoted, list or dict value is not valid yaml
+pub fn parse_pair(pair: &str) -> Result<(String, Value), Box<dyn std::error::Error>> {
+    let Some((key, value)) = pair.split_once('=') else {
+        return Err(format!("Extra var must be in the format KEY=VALUE: {}", pair).into());
+    };
+    let key = unescape(key.trim());
+    if key.is_empty() {
+        return Err(format!("Extra var must be in the format KEY=VALUE: {}", pair).into());
+    }
+    Ok((key, parse_value(value)?))
+}
+
+/// Implements the split unquoted function, which splits the text on every `separator` that
+/// is not inside quotes or brackets and not escaped with a backslash
+pub fn split_unquoted(text: &str, separator: char) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quote = None;
+    let mut depth = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (None, '\\') => {
+                part.push(c);
+                if let Some(next) = chars.next() {
+                    part.push(next);
+                }
+                continue;
+            }
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '[' | '{') => depth += 1,
+            (None, ']' | '}') => depth -= 1,
+            (None, _) if c == separator && depth <= 0 => {
+                parts.push(std::mem::take(&mut part));
+                continue;
+            }
+            _ => {}
+        }
+        part.push(c);
+    }
+    parts.push(part);
+    parts.retain(|part| !part.trim().is_empty());
+    parts
+}
+
+/// Implements the load vars file function, which reads a yaml (or json) file holding a
+/// mapping of variable names to values
+///
+/// # Errors
+/// Returns an error if the file cannot be read
+/// Returns an error if the file is not a yaml mapping
+pub fn load_vars_file(path: &Path) -> Result<ExtraVars, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)
+        .map_err(|err| format!("Vars file {} is not readable: {}", path.display(), err))?;
+    let vars: Option<ExtraVars> = serde_yaml::from_str(&contents)
+        .map_err(|err| format!("Vars file {} is not a mapping: {}", path.display(), err))?;
+    Ok(vars.unwrap_or_default())
+}
+
+/// Implements the book vars function, which merges all the places the extra vars of a
+/// playbook can come from. Later places win over earlier ones:
+/// 1. `global`, the vars of every `--vars-file`
+/// 2. `files`, the `@file` vars files given for the playbook, in order
+/// 3. the env variable named after the playbook, e.g. from the `.env` file
+/// 4. `inline`, the `KEY=VALUE` vars given for the playbook
+///
+/// # Errors
+/// Returns an error if a vars file cannot be read
+/// Returns an error if the env variable is misformatted
+pub fn book_vars(
+    book: &str,
+    global: &ExtraVars,
+    files: &[PathBuf],
+    inline: &ExtraVars,
+) -> Result<ExtraVars, Box<dyn std::error::Error>> {
+    let mut vars = global.clone();
+    for file in files {
+        vars.extend(load_vars_file(file)?);
+    }
+    if let Ok(envs) = env::var(book) {
+        let env_vars =
+            parse_vars(&envs).map_err(|err| format!("Misformatted env var {}: {}", book, err))?;
+        vars.extend(env_vars);
+    }
+    vars.extend(inline.clone());
+    Ok(vars)
+}
+
+/// Implements the format value function, which gives a value the way it would be typed on
+/// the command line: strings as they are, everything else as json
+pub fn format_value(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Implements the format pair function, which gives a variable as a `KEY=VALUE` pair that
+/// `parse_pair` reads back as the same value. Strings that would otherwise be typed or split
+/// are put in single quotes
+pub fn format_pair(key: &str, value: &Value) -> String {
+    let formatted = match value {
+        Value::String(text) => {
+            let plain = !text.is_empty()
+                && !text.contains([',', '\\', '\'', '"', '[', ']', '{', '}'])
+                && !text.starts_with(char::is_whitespace)
+                && !text.ends_with(char::is_whitespace)
+                && matches!(parse_value(text), Ok(Value::String(_)));
+            if plain {
+                text.clone()
+            } else {
+                format!("'{}'", text.replace('\'', "''"))
+            }
+        }
+        value => value.to_string(),
+    };
+    format!("{}={}", key, formatted)
+}
+
+/// Implements the is secret function, which gives whether the value of a variable is a
+/// secret by its name, e.g. `db_password` or `api_token`. Secrets are not shown while they
+/// are typed and are not written to history or reports, see `redact`
+pub fn is_secret(name: &str) -> bool {
+    let name = name.to_lowercase();
+    SECRET_NAMES.iter().any(|secret| name.contains(secret))
+}
+
+/// Implements the redact function, which gives the extra vars with the value of every secret
+/// (see `is_secret`) replaced by `REDACTED`
+pub fn redact(vars: &ExtraVars) -> ExtraVars {
+    let mut redacted = vars.clone();
+    for (name, value) in redacted.iter_mut() {
+        if is_secret(name) {
+            *value = Value::String(REDACTED.to_owned());
+        }
+    }
+    redacted
+}
+
+/// Types a single value, see `parse_vars`
+fn parse_value(value: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let trimmed = value.trim();
+    if trimmed.starts_with(['"', '\'', '[', '{']) && !trimmed.starts_with("{{") {
+        return Ok(serde_yaml::from_str(trimmed)?);
+    }
+    let value = unescape(value);
+    let typed = match value.as_str() {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        "null" => Value::Null,
+        _ => match serde_json::from_str::<serde_json::Number>(&value) {
+            Ok(number) if number.to_string() == value => Value::Number(number),
+            _ => Value::String(value),
+        },
+    };
+    Ok(typed)
+}
+
+/// Drops the backslashes that escape the character after them
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                result.push(next);
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn split_unquoted_splits_on_bare_separators() {
+        assert_eq!(split_unquoted("a=1,b=2", ','), vec!["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn split_unquoted_keeps_quoted_and_bracketed_separators() {
+        assert_eq!(
+            split_unquoted("a='x,y',b=\"p,q\",c=[1,2],d={k: 1, l: 2}", ','),
+            vec!["a='x,y'", "b=\"p,q\"", "c=[1,2]", "d={k: 1, l: 2}"]
+        );
+    }
+
+    #[test]
+    fn split_unquoted_keeps_escaped_separators() {
+        assert_eq!(split_unquoted("a=x\\,y,b=2", ','), vec!["a=x\\,y", "b=2"]);
+    }
+
+    #[test]
+    fn split_unquoted_drops_empty_parts() {
+        assert_eq!(split_unquoted(",a=1,, ,b=2,", ','), vec!["a=1", "b=2"]);
+        assert!(split_unquoted("", ',').is_empty());
+    }
+
+    #[test]
+    fn parse_value_types_scalars() {
+        assert_eq!(parse_value("true").unwrap(), json!(true));
+        assert_eq!(parse_value("false").unwrap(), json!(false));
+        assert_eq!(parse_value("null").unwrap(), json!(null));
+        assert_eq!(parse_value("8080").unwrap(), json!(8080));
+        assert_eq!(parse_value("1.5").unwrap(), json!(1.5));
+        assert_eq!(parse_value("hello").unwrap(), json!("hello"));
+    }
+
+    #[test]
+    fn parse_value_keeps_quoted_text_as_text() {
+        assert_eq!(parse_value("'8080'").unwrap(), json!("8080"));
+        assert_eq!(parse_value("\"true\"").unwrap(), json!("true"));
+    }
+
+    #[test]
+    fn parse_value_reads_lists_and_maps() {
+        assert_eq!(parse_value("[1, two]").unwrap(), json!([1, "two"]));
+        assert_eq!(
+            parse_value("{k: v, n: 1}").unwrap(),
+            json!({"k": "v", "n": 1})
+        );
+    }
+
+    #[test]
+    fn parse_value_unescapes_plain_text() {
+        assert_eq!(parse_value("a\\,b").unwrap(), json!("a,b"));
+    }
+
+    #[test]
+    fn parse_value_keeps_numbers_that_would_change_as_text() {
+        assert_eq!(parse_value("1.10").unwrap(), json!("1.10"));
+        assert_eq!(parse_value("1e3").unwrap(), json!("1e3"));
+        assert_eq!(parse_value("007").unwrap(), json!("007"));
+        assert_eq!(parse_value("-3").unwrap(), json!(-3));
+    }
+
+    #[test]
+    fn parse_value_keeps_jinja_as_text() {
+        assert_eq!(parse_value("{{ other }}").unwrap(), json!("{{ other }}"));
+        assert_eq!(
+            parse_pair("msg={{ a }}-{{ b }}").unwrap(),
+            ("msg".to_owned(), json!("{{ a }}-{{ b }}"))
+        );
+    }
+
+    #[test]
+    fn redact_hides_secrets_only() {
+        let vars: ExtraVars = [
+            ("db_password".to_owned(), json!("hunter2")),
+            ("API_TOKEN".to_owned(), json!(42)),
+            ("user".to_owned(), json!("bob")),
+        ]
+        .into_iter()
+        .collect();
+        let redacted = redact(&vars);
+        assert_eq!(redacted["db_password"], json!(REDACTED));
+        assert_eq!(redacted["API_TOKEN"], json!(REDACTED));
+        assert_eq!(redacted["user"], json!("bob"));
+    }
+
+    #[test]
+    fn parse_value_rejects_broken_yaml() {
+        assert!(parse_value("[1, 2").is_err());
+    }
+}